@@ -0,0 +1,135 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// Commands the run loop accepts while running, one byte each except for the window size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    /// `p`: stops scrolling or resumes it.
+    TogglePause,
+    /// `r`: polls the source again and starts over from the beginning of its content.
+    Restart,
+    /// `q`: stops the run loop.
+    Quit,
+    /// A number on its own line: changes the window size keeping the position.
+    /// The run loop ignores 0 and sizes wider than the output field.
+    Window(usize),
+}
+
+impl Control {
+    pub fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            b'p' => Some(Control::TogglePause),
+            b'r' => Some(Control::Restart),
+            b'q' => Some(Control::Quit),
+            _ => None,
+        }
+    }
+}
+
+/// Turns a stream of bytes into commands, digits are collected until a newline.
+#[derive(Debug, Default)]
+struct Parser {
+    number: Option<usize>,
+}
+
+impl Parser {
+    fn feed(&mut self, b: u8) -> Option<Control> {
+        match b {
+            b'0'..=b'9' => {
+                let digit = (b - b'0') as usize;
+                self.number = Some(
+                    self.number
+                        .unwrap_or_default()
+                        .saturating_mul(10)
+                        .saturating_add(digit),
+                );
+                None
+            }
+            b'\n' => self.number.take().map(Control::Window),
+            _ => {
+                self.number = None;
+                Control::from_byte(b)
+            }
+        }
+    }
+}
+
+/// Reads commands from `reader` on a background thread, unknown bytes are ignored.
+/// The channel is closed once the reader reaches its end or fails.
+pub fn spawn<R: Read + Send + 'static>(reader: R) -> Receiver<Control> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || forward(reader, &sender));
+    receiver
+}
+
+/// Like `spawn`, but reads commands from a file.
+/// Anything but a regular file, e.g. a FIFO, is opened again each time its writer goes away.
+pub fn spawn_file(path: PathBuf) -> Receiver<Control> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(file) = File::open(&path) {
+            if !forward(file, &sender) || path.is_file() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+/// Sends commands from `reader` until its end, returns `false` if the receiver is gone.
+fn forward<R: Read>(mut reader: R, sender: &Sender<Control>) -> bool {
+    let mut parser = Parser::default();
+    let mut buf = [0; 64];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return true,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return true,
+        };
+        for control in buf[..n].iter().filter_map(|b| parser.feed(*b)) {
+            if sender.send(control).is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io::Cursor, process};
+
+    use anyhow::Result;
+
+    use super::{spawn, spawn_file, Control};
+
+    #[test]
+    fn commands() {
+        let receiver = spawn(Cursor::new(b"p\nxrq40\n4x2\n\n".to_vec()));
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            [
+                Control::TogglePause,
+                Control::Restart,
+                Control::Quit,
+                Control::Window(40),
+                Control::Window(2)
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_from_file() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-control-file-{}", process::id()));
+        fs::write(&path, "12\nq")?;
+        let commands = spawn_file(path.clone()).iter().collect::<Vec<_>>();
+        fs::remove_file(&path)?;
+        assert_eq!(commands, [Control::Window(12), Control::Quit]);
+        Ok(())
+    }
+}
@@ -1,76 +1,285 @@
+mod control;
+mod output;
 mod running_text;
+mod stats;
 mod utils;
 mod text_source;
 #[cfg(feature = "mpd")]
 mod mpd;
 
 use std::{
+    env,
     fs,
     io,
     path::PathBuf,
+    process::{self, Stdio},
+    thread,
     time::Duration, ffi::OsString,
 };
 #[cfg(feature = "mpd")]
 use std::net::SocketAddr;
 
-use anyhow::anyhow;
+use anyhow::{anyhow, Context};
 use clap::{
     arg, command, crate_description, crate_name, value_parser, ArgAction, ArgGroup, ArgMatches, Command, ValueHint
 };
 use text_source::TextSource;
 
-use crate::running_text::RunningText;
+use crate::{output::{Alignment, Output, When}, running_text::{RunningText, SeparatorMode}, utils::{unescape, WrapTemplate}};
 
 #[cfg(feature = "mpd")]
-use crate::mpd::{StatusIcons, StateStatusIcons, MpdFormatter};
+use crate::mpd::{IconTheme, MpdFormatter};
+
+/// Splits `text` on every `delimiter` not escaped with a backslash, escapes are kept for `unescape`.
+fn split_unescaped(text: &str, delimiter: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == delimiter => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
 
 fn parse_key_value_pair(value: &str) -> anyhow::Result<(String, String)> {
-    value.split_once('=').map(|(l, r)| (l.to_owned(), r.to_owned())).ok_or(anyhow!("Key-value pair must have at least one '=' sign"))
+    let l = match split_unescaped(value, '=')[..] {
+        [l, _, ..] => l,
+        _ => return Err(anyhow!("Key-value pair must have at least one unescaped '=' sign")),
+    };
+    Ok((unescape(l)?, unescape(&value[l.len() + 1..])?))
+}
+
+/// Reads comma separated `src=dest` pairs, a literal comma or equals sign is escaped with a backslash.
+fn parse_key_value_pairs(value: &str) -> anyhow::Result<Vec<(String, String)>> {
+    split_unescaped(value, ',').into_iter().map(parse_key_value_pair).collect()
+}
+
+/// Reads `src=dest` pairs, one per line, empty lines and lines starting with '#' are skipped.
+fn replacements_from_file(path: &PathBuf) -> anyhow::Result<Vec<(String, String)>> {
+    fs::read_to_string(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|contents| {
+            contents
+                .lines()
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(parse_key_value_pair)
+                .collect()
+        })
+        .map_err(|e| e.context(format!("Failed reading replacements file {}", path.display())))
+}
+
+/// Environment variable holding the new contents for the --on-change command.
+const CONTENT_VAR: &str = "MERGNEH_CONTENT";
+
+/// Runs a command in the background each time the contents change, its output is discarded.
+fn on_change_hook(args: Vec<OsString>) -> impl FnMut(&str) -> anyhow::Result<()> {
+    let mut cmd = process::Command::new(&args[0]);
+    cmd.args(&args[1..]).stdout(Stdio::null());
+    move |content| {
+        let mut child = cmd.env(CONTENT_VAR, content).spawn().context("Failed running the on-change command")?;
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+const DEFAULT_WINDOW: usize = 32;
+
+/// Parses a window size, `auto` asks the terminal for its width and falls back to `COLUMNS`.
+fn parse_window(value: &str) -> anyhow::Result<usize> {
+    let window = match value {
+        "auto" => window_from_terminal(utils::terminal_columns(), env::var("COLUMNS")),
+        _ => value.parse()?,
+    };
+    if window == 0 {
+        return Err(anyhow!("Window size must be at least 1"));
+    }
+    Ok(window)
+}
+
+fn parse_speed(value: &str) -> anyhow::Result<f64> {
+    let speed = value.parse::<f64>()?;
+    if !speed.is_finite() || speed <= 0.0 {
+        return Err(anyhow!("Speed must be a positive number"));
+    }
+    Ok(speed)
+}
+
+/// The width of the terminal if there is one, then `COLUMNS`, then the default.
+fn window_from_terminal(terminal: Option<usize>, columns: Result<String, env::VarError>) -> usize {
+    terminal.filter(|&c| c > 0).unwrap_or_else(|| window_from_columns(columns))
+}
+
+fn window_from_columns(columns: Result<String, env::VarError>) -> usize {
+    columns
+        .ok()
+        .and_then(|c| c.trim().parse().ok())
+        .filter(|&c| c > 0)
+        .unwrap_or(DEFAULT_WINDOW)
+}
+
+fn offset_from_env(value: Result<String, env::VarError>) -> anyhow::Result<usize> {
+    match value {
+        Ok(offset) => offset.parse::<usize>().map_err(|e| anyhow!(e).context("Failed parsing offset variable")),
+        Err(env::VarError::NotPresent) => Ok(0),
+        Err(e) => Err(anyhow!(e).context("Failed reading offset variable")),
+    }
+}
+
+fn offset_assignment(var: &str, offset: usize) -> String {
+    format!("{var}={offset}")
 }
 
 fn text_from_matches(matches: &mut ArgMatches) -> anyhow::Result<RunningText> {
-    RunningText::new(
+    let mut replacements = match matches.remove_one::<PathBuf>("replacements-file") {
+        Some(path) => replacements_from_file(&path)?,
+        None => vec![],
+    };
+    replacements.extend(
+        matches.remove_many::<Vec<(String, String)>>("replacements").into_iter().flatten().flatten()
+    );
+    let mut text = RunningText::new(
         TextSource::try_from(&mut *matches)?,
-        matches.remove_one::<u64>("window").unwrap() as usize,
+        matches.remove_one("window").unwrap(),
         matches.remove_one("separator").unwrap(),
-        matches.remove_one("newline").unwrap(),
-        matches.remove_many("replacements").unwrap_or_default().collect(),
+        if matches.remove_one::<bool>("keep-newlines").unwrap() {
+            "\n".to_owned()
+        } else {
+            matches.remove_one("newline").unwrap()
+        },
+        replacements,
         matches.remove_one("dont-repeat").unwrap(),
         matches.remove_one("reset-on-change").unwrap(),
-    )
+    )?
+    .word_scroll(matches.remove_one("word-scroll").unwrap())
+    .skip_identical(matches.remove_one("skip-identical").unwrap())
+    .cycles(matches.remove_one::<u64>("cycles").map(|n| n as usize))
+    .speed(matches.remove_one("speed").unwrap())
+    .initial(matches.remove_one("initial"))?
+    .scroll_once(matches.remove_one("scroll-once").unwrap())?
+    .once_then_clear(matches.remove_one("once-then-clear").unwrap())?
+    .truncate_to_window(matches.remove_one("truncate").unwrap())?
+    .separator_mode(matches.remove_one("separator-mode").unwrap());
+    if let Some(max_len) = matches.remove_one::<u64>("max-len") {
+        text = text.max_len(max_len as usize, matches.remove_one("ellipsis"))?;
+    }
+    if let Some(args) = matches.remove_many::<OsString>("on-change") {
+        text = text.on_change(on_change_hook(args.collect()));
+    }
+    if matches.remove_one::<bool>("start-at-end").unwrap() {
+        text.seek_end();
+    }
+    if let Some(offset) = matches.remove_one::<u64>("start-offset") {
+        text.seek_clamped(offset as usize);
+    }
+    Ok(text)
 }
 
-fn main() -> anyhow::Result<()> {
-    // TODO:
-    // - support for multiple running texts (like each one has its own source etc)
-    // - support for long texts (without reading whole content)
-    // - --once option for run subcommand
+/// Builds the source once, returns its description if it works.
+fn check(matches: &mut ArgMatches) -> anyhow::Result<String> {
+    let mut explanation = String::new();
+    text_from_matches(matches)?.explain(&mut explanation)?;
+    Ok(explanation.lines().next().unwrap_or_default().to_owned())
+}
+
+/// The normalized format and its placeholders,
+/// or the parsing error with the format pointed at where parsing stopped.
+#[cfg(feature = "mpd")]
+fn format_check(format: &str) -> Result<String, String> {
+    match MpdFormatter::parse_with_position(format) {
+        Ok(formatter) => Ok(format!(
+            "{formatter}\nplaceholders: {}",
+            formatter.placeholder_specs().join(", ")
+        )),
+        Err((e, position)) => {
+            let column = format[..position].chars().count() + 1;
+            Err(format!("{e} at column {column}:\n{format}\n{:>column$}", "^"))
+        }
+    }
+}
+
+/// A source is not required for `format-check`, so every other subcommand is checked for it here.
+fn require_source(cli: &mut Command, matches: &ArgMatches) -> Result<(), clap::Error> {
+    if matches.contains_id("sources") || matches.subcommand_name() == Some("format-check") {
+        return Ok(());
+    }
+    Err(cli.error(
+        clap::error::ErrorKind::MissingRequiredArgument,
+        "a source is required: SOURCE, --file, --string, --stdin, --cmd or --mpd",
+    ))
+}
+
+fn cli() -> Command {
     let cli = command!(crate_name!())
         .about(crate_description!())
-        .arg(arg!(-w --window <WINDOW> "Window size").value_parser(value_parser!(u64).range(1..)).default_value("32"))
-        .arg(arg!(-s --separator <SEP> "String to print between content").default_value(""))
-        .arg(arg!(-n --newline <NL> "String to replace newlines with").default_value(""))
+        .arg(arg!(-w --window <WINDOW> "Window size, 'auto' takes the terminal width or the COLUMNS variable").value_parser(parse_window).default_value("32"))
+        .arg(arg!(-s --separator <SEP> "String to print between content").value_parser(unescape).default_value(""))
+        .arg(arg!(--"separator-mode" <MODE> "Whether the window can start inside the separator or jumps over it")
+             .value_parser(value_parser!(SeparatorMode))
+             .default_value("in-scroll"))
+        .arg(arg!(-n --newline <NL> "String to replace newlines with").value_parser(unescape).default_value(""))
+        .arg(arg!(--"keep-newlines" "Keep newlines in contents instead of replacing them").conflicts_with("newline"))
         .arg(arg!(-l --prefix <PREFIX> "String to print before running text").default_value(""))
         .arg(arg!(-r --suffix <SUFFIX> "String to print after running text").default_value(""))
         .arg(arg!(-'1' --"dont-repeat" "Do not repeat contents if it fits in the window size").action(ArgAction::SetFalse))
         .arg(arg!(--"reset-on-change" "Reset text window on content change"))
+        .arg(arg!(--"start-at-end" "Start with the end of the content in the window"))
+        .arg(arg!(--"start-offset" <N> "Start N characters into the content, at most at its last character")
+             .value_parser(value_parser!(u64))
+             .conflicts_with("start-at-end"))
+        .arg(arg!(--"word-scroll" "Scroll by words instead of characters"))
+        .arg(arg!(--"skip-identical" "Skip iterations identical to the previous one"))
+        .arg(arg!(--speed <SPEED> "Characters to scroll by per iteration, e.g. 0.5 to move every other iteration").value_parser(parse_speed).default_value("1"))
+        .arg(arg!(--cycles <N> "Stop scrolling after N full cycles and hold the beginning").value_parser(value_parser!(u64)))
+        .arg(arg!(--"scroll-once" "Scroll contents that do not fit once and hold the end, keep the rest static").conflicts_with("cycles"))
+        .arg(arg!(--"once-then-clear" "Scroll contents once, then write empty iterations until they change").conflicts_with_all(["cycles", "scroll-once"]))
+        .arg(arg!(--truncate "Cut contents that do not fit to the window instead of scrolling them").conflicts_with_all(["cycles", "scroll-once", "once-then-clear"]))
+        .arg(arg!(--"max-len" <N> "Truncate contents to at most N graphemes").value_parser(value_parser!(u64)))
+        .arg(arg!(--ellipsis [INDICATOR] "End truncated contents with an indicator ('…' if not given)").default_missing_value("…").requires("max-len"))
+        .arg(arg!(--"dry-run" "Print the resulting configuration instead of running").visible_alias("explain"))
         .arg(arg!(-e --replacements <REPLACE> "Key-value pairs of replacements. Specified as 'src=dest'.
 Multiple replacements can be passed either as one argument separated by comma: -e src1=dest1,src2=dest2
 or as separated arguments: -e src1=dest1 -e src2=dest2.
-Order of replacements matters. Useful for escaping special characters.")
-             .value_delimiter(',')
-             .value_parser(parse_key_value_pair)
+Order of replacements matters. Useful for escaping special characters.
+Separator, newline and replacements support escape sequences: \\n, \\t, \\r, \\\\ and \\uXXXX,
+a literal comma or equals sign is written as \\, or \\=.")
+             .value_parser(parse_key_value_pairs)
              .action(ArgAction::Append))
+        .arg(arg!(--"replacements-file" <PATH> "File with a 'src=dest' replacement per line ('#' starts a comment), applied before --replacements")
+             .value_parser(value_parser!(PathBuf))
+             .value_hint(ValueHint::FilePath))
+        .arg(arg!(--"on-change" <ARGS> ... "Run a command in the background each time the contents change, they are passed in MERGNEH_CONTENT (use a ';' as a terminator)")
+             .value_parser(value_parser!(OsString))
+             .value_hint(ValueHint::CommandName)
+             .num_args(1..)
+             .value_terminator(";"))
         .next_help_heading("Sources")
         .arg(arg!(<SOURCE> "    Same as --file, if file with this name does not exist or is a directory, it will behave as --string"))
         .arg(arg!(-f --file <FILE> "Pull contents from a file (BEWARE: it loads whole file into memory!)"))
+        .arg(arg!(--lossy "Replace invalid UTF-8 in files with U+FFFD instead of failing"))
+        .arg(arg!(--initial <TEXT> "Text to show until the source produces any contents"))
         .arg(arg!(-S --string <STRING> "Use a string as contents"))
+        .arg(arg!(--"string-escapes" "Interpret escape sequences in --string").requires("string"))
         .arg(arg!(--stdin "Pull contents from stdin (BEWARE: it loads whole input into memory just like --file)"))
-        .arg(arg!(--cmd <ARGS> ... "Execute a command and use its output as contents (use a ';' as a terminator)")
+        .arg(arg!(--cmd <ARGS> ... "Execute a command and use its output as contents (use a ';' as a terminator, '\\;' for a literal ';').
+The command gets the offset of the next iteration in MERGNEH_OFFSET")
              .value_parser(value_parser!(OsString))
              .value_hint(ValueHint::CommandName)
              .num_args(1..)
              .value_terminator(";"))
+        .arg(arg!(--wrap <TEMPLATE> "Template around the contents: {content}, its length as {len} and number of lines as {lines}")
+             .value_parser(value_parser!(WrapTemplate)))
+        .arg(arg!(--"keep-trailing-newline" "Do not strip the trailing newline from the command output").requires("cmd"))
+        .arg(arg!(--delta [UNIT] "Show the difference between consecutive numbers from the command followed by a unit").requires("cmd")
+             .default_missing_value(""))
         .group(
             ArgGroup::new("sources")
             .required(true)
@@ -83,13 +292,53 @@ Order of replacements matters. Useful for escaping special characters.")
                      .value_parser(value_parser!(humantime::Duration))
                      .default_value("1s"))
                 .arg(arg!(-n --newline "Print each iteration on next line"))
+                .arg(arg!(--"align-ticks" "Tick on multiples of the duration since the Unix epoch, e.g. on whole seconds"))
+                .arg(arg!(--stats [INTERVAL] "Print the tick rate, work per tick and source changes to stderr every interval")
+                     .value_parser(value_parser!(humantime::Duration))
+                     .default_missing_value("10s"))
+                .arg(arg!(-v --verbose "Log source changes, errors and failing commands to stderr instead of stopping on errors"))
+                .arg(arg!(--"stdin-control" "Read commands from stdin: 'p' to pause or resume, 'r' to reload the source and start over, 'q' to quit, a number on its own line to change the window size"))
+                .arg(arg!(--control <PATH> "Read commands like --stdin-control from a file or a FIFO, reopened each time its writer goes away")
+                     .value_parser(value_parser!(PathBuf))
+                     .value_hint(ValueHint::FilePath)
+                     .conflicts_with("stdin-control"))
+                .arg(arg!(-o --output <PATH> "Write iterations to a file or a FIFO ('-' for stdout)")
+                     .value_parser(value_parser!(PathBuf))
+                     .value_hint(ValueHint::FilePath)
+                     .default_value("-"))
+                .arg(arg!(--socket <PATH> "Bind a Unix socket and write iterations to every connected client")
+                     .value_parser(value_parser!(PathBuf))
+                     .value_hint(ValueHint::FilePath)
+                     .conflicts_with("output"))
+                .arg(arg!(--"clear-line" [WHEN] "Erase the rest of the line after each iteration (auto: only when writing to a terminal)")
+                     .value_parser(value_parser!(When))
+                     .default_value("auto")
+                     .default_missing_value("always"))
+                .arg(arg!(--binary "Write each iteration as its length in 4 little-endian bytes followed by its UTF-8 bytes")
+                     .conflicts_with_all(["clear-line", "newline"]))
+                .arg(arg!(--"field-width" <WIDTH> "Pad each iteration with spaces up to this width (must not be less than the window size)")
+                     .value_parser(value_parser!(u64)))
+                .arg(arg!(--align <ALIGNMENT> "Alignment of an iteration inside its field")
+                     .value_parser(value_parser!(Alignment))
+                     .default_value("right")
+                     .requires("field-width"))
                 .about("Run text in a terminal")
         )
+        .subcommand(
+            Command::new("check")
+                .about("Check that the source works and exit")
+        )
+        .subcommand(
+            Command::new("cycle")
+                .about("Print every iteration of one full cycle")
+        )
         .subcommand(
             Command::new("iter")
-                .arg(arg!(<ITER_FILE> "File containing data for next iteration")
+                .arg(arg!([ITER_FILE] "File containing data for next iteration")
                      .value_parser(value_parser!(PathBuf))
                      .value_hint(ValueHint::FilePath))
+                .arg(arg!(--"offset-env" <VAR> "Read the offset from an environment variable instead of a file, the next one is printed to stderr as VAR=<offset>"))
+                .group(ArgGroup::new("state").required(true).args(["ITER_FILE", "offset-env"]))
                 .about("Print just one iteration")
                 .arg_required_else_help(true),
         );
@@ -98,45 +347,50 @@ Order of replacements matters. Useful for escaping special characters.")
         .arg(
             arg!(--mpd [SERVER_ADDR] "Display MPD status as running text [default server address is 127.0.0.0:6600]")
             .group("sources")
+            .conflicts_with("wrap")
             .value_parser(value_parser!(SocketAddr))
             .default_missing_value("127.0.0.0:6600")
         )
         .next_help_heading("MPD Options")
         .arg(
-            arg!(--"status-icons" <ICONS> "Status icons to use")
-            .value_parser(value_parser!(StateStatusIcons))
-            .default_value(""),
+            arg!(--"icon-theme" <THEME> "Built-in set of icons, the icon options below override its icons")
+            .value_parser(value_parser!(IconTheme))
+            .default_value("nerdfont")
+        )
+        .arg(
+            arg!(--"status-icons" <ICONS> "Status icons to use"),
         )
         .arg(
             arg!(--"repeat-icons" <ICONS> "Repeat icons to use")
-            .value_parser(value_parser!(StatusIcons))
-            .default_value("")
             .requires("mpd")
         )
         .arg(
             arg!(--"consume-icons" <ICONS> "Consume icons to use")
-            .value_parser(value_parser!(StatusIcons))
-            .default_value("")
             .requires("mpd")
         ) 
         .arg(
             arg!(--"random-icons" <ICONS> "Random icons to use")
-            .value_parser(value_parser!(StatusIcons))
-            .default_value("")
             .requires("mpd")
         ) 
         .arg(
             arg!(--"single-icons" <ICONS> "Single icons to use")
-            .value_parser(value_parser!(StatusIcons))
-            .default_value("")
             .requires("mpd")
         ) 
+        .arg(
+            arg!(--"icon-separator" <SEP> "Separator between icons in the icon options, each character is an icon without it")
+            .requires("mpd")
+        )
         .arg(
             arg!(--format <FORMAT> "Format string to use in running text")
             .value_parser(value_parser!(MpdFormatter))
             .default_value("{artist} - {title}")
             .requires("mpd")
         )
+        .arg(
+            arg!(--"stopped-format" <FORMAT> "Format string to use in running text when MPD is stopped")
+            .value_parser(value_parser!(MpdFormatter))
+            .requires("mpd")
+        )
         .arg(
             arg!(-L --"prefix-format" <FORMAT> "Format string to use in prefix")
             .value_parser(value_parser!(MpdFormatter))
@@ -153,19 +407,105 @@ Order of replacements matters. Useful for escaping special characters.")
             arg!(-D --"default-placeholder" <PLACEHOLDER> "Default placeholder for missing values")
             .default_value("N/A")
             .requires("mpd")
-        );
+        )
+        .arg(
+            arg!(--"hide-when-stopped" "Print empty iterations while MPD is stopped or has no song")
+            .requires("mpd")
+        )
+        .arg(
+            arg!(--"escape-braces" "Double every brace in the output, so it reads as a format")
+            .requires("mpd")
+        )
+        .subcommand(
+            Command::new("format-check")
+                .arg(arg!(<FORMAT> "MPD format string"))
+                .about("Check an MPD format string and print it normalized along with its placeholders")
+        )
+        .subcommand_negates_reqs(true);
+    cli
+}
 
-    let mut matches = cli.get_matches();
+fn main() -> anyhow::Result<()> {
+    // TODO:
+    // - support for multiple running texts (like each one has its own source etc)
+    // - support for long texts (without reading whole content)
+    // - --once option for run subcommand
+    let mut cli = cli();
+    let mut matches = cli.get_matches_mut();
+    #[cfg(feature = "mpd")]
+    if let Some(("format-check", sub_matches)) = matches.subcommand() {
+        match format_check(sub_matches.get_one::<String>("FORMAT").unwrap()) {
+            Ok(report) => println!("{report}"),
+            Err(report) => {
+                eprintln!("error: {report}");
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+    if let Err(e) = require_source(&mut cli, &matches) {
+        e.exit();
+    }
+    let dry_run = matches.remove_one::<bool>("dry-run").unwrap();
+    let window = *matches.get_one::<usize>("window").unwrap();
+    let stdin_source = matches.get_flag("stdin");
+    if matches.subcommand_name() == Some("check") {
+        match check(&mut matches) {
+            Ok(source) => println!("ok: {source}"),
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
     let mut text = text_from_matches(&mut matches)?;
+    if dry_run {
+        let mut explanation = String::new();
+        text.explain(&mut explanation)?;
+        print!("{explanation}");
+        return Ok(());
+    }
     let (cmd, mut sub_matches) = matches.remove_subcommand().unwrap();
     match cmd.as_str() {
         "run" => {
             let duration: Duration = sub_matches
                 .remove_one::<humantime::Duration>("duration")
                 .unwrap().into();
-            text.run_on_terminal(duration, sub_matches.remove_one("newline").unwrap())?;
+            let mut output = match sub_matches.remove_one::<PathBuf>("socket") {
+                Some(path) => Output::socket(path)?,
+                None => Output::open(sub_matches.remove_one("output").unwrap())?,
+            }
+                .clear_line(sub_matches.remove_one("clear-line").unwrap())
+                .binary(sub_matches.remove_one("binary").unwrap());
+            if let Some(width) = sub_matches.remove_one::<u64>("field-width") {
+                if (width as usize) < window {
+                    return Err(anyhow!("Field width ({width}) must not be less than the window size ({window})"));
+                }
+                output = output.field(width as usize, sub_matches.remove_one("align").unwrap());
+            }
+            let stdin_control = sub_matches.remove_one::<bool>("stdin-control").unwrap();
+            if stdin_control && stdin_source {
+                return Err(anyhow!("--stdin-control cannot be used with --stdin"));
+            }
+            text.stats(sub_matches.remove_one::<humantime::Duration>("stats").map(Into::into)).run(
+                duration,
+                sub_matches.remove_one("newline").unwrap(),
+                &mut output,
+                sub_matches.remove_one::<bool>("verbose").unwrap().then(io::stderr),
+                match sub_matches.remove_one::<PathBuf>("control") {
+                    Some(path) => Some(control::spawn_file(path)),
+                    None => stdin_control.then(|| control::spawn(io::stdin())),
+                },
+                sub_matches.remove_one("align-ticks").unwrap(),
+            )?;
         }
         "iter" => {
+            if let Some(var) = sub_matches.remove_one::<String>("offset-env") {
+                let i = text.print_once(offset_from_env(env::var(&var))?, None)?;
+                eprintln!("{}", offset_assignment(&var, i));
+                return Ok(());
+            }
             let iter_file = sub_matches.remove_one::<PathBuf>("ITER_FILE").unwrap();
             let (i, prev_content) = match fs::read_to_string(&iter_file) {
                 Ok(s) => match s.split_once(' ') {
@@ -182,11 +522,224 @@ Order of replacements matters. Useful for escaping special characters.")
                     _ => return Err(e.into()),
                 },
             };
-            let i = text.print_once(i, prev_content.as_str())?;
+            let i = text.print_once(i, Some(prev_content.as_str()))?;
             fs::write(iter_file, format!("{i} {}", text.get_raw_content()))?;
         }
+        "cycle" => {
+            for frame in text.full_cycle()? {
+                println!("{frame}");
+            }
+        }
         _ => unreachable!(),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use std::{env, fs, process};
+
+    use super::{check, cli, offset_assignment, offset_from_env, require_source, text_from_matches, window_from_columns, window_from_terminal, DEFAULT_WINDOW};
+    #[cfg(feature = "mpd")]
+    use super::{format_check, MpdFormatter};
+
+    #[test]
+    fn explain() -> Result<()> {
+        let mut matches = cli().try_get_matches_from([
+            "mg",
+            "-S", "I am a running text",
+            "-w", "12",
+            "-s", " | ",
+            "-e", "&=&amp;",
+            "-1",
+            "--speed", "2",
+            "--max-len", "10",
+            "--dry-run",
+            "run",
+        ])?;
+        let mut explanation = String::new();
+        text_from_matches(&mut matches)?.explain(&mut explanation)?;
+        assert_eq!(
+            explanation,
+            r#"source: string
+window: 12
+scroll mode: Overflow
+reset on change: false
+word scroll: false
+speed: 2.0
+cycles: None
+max length: Some(10)
+ellipsis: None
+separator: " | "
+separator mode: InScroll
+newline: "\u{f810}"
+prefix: ""
+suffix: ""
+replacements: [("&", "&amp;")]
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_separator() -> Result<()> {
+        let mut matches = cli().try_get_matches_from([
+            "mg",
+            "-S", "text",
+            "-s", r"\t",
+            "-n", r"\u2424",
+            "-e", r"\\=\n",
+            "run",
+        ])?;
+        assert_eq!(matches.remove_one::<String>("separator").unwrap(), "\t");
+        assert_eq!(matches.remove_one::<String>("newline").unwrap(), "\u{2424}");
+        assert_eq!(
+            matches.remove_many::<Vec<(String, String)>>("replacements").unwrap().flatten().collect::<Vec<_>>(),
+            vec![("\\".to_owned(), "\n".to_owned())]
+        );
+        assert!(cli().try_get_matches_from(["mg", "-S", "text", "-s", r"\q", "run"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_delimiters() -> Result<()> {
+        let mut matches = cli().try_get_matches_from([
+            "mg",
+            "-S", "text",
+            "-e", r"\,=;,a\=b=c=d,\\=/",
+            "run",
+        ])?;
+        assert_eq!(
+            matches.remove_many::<Vec<(String, String)>>("replacements").unwrap().flatten().collect::<Vec<_>>(),
+            vec![
+                (",".to_owned(), ";".to_owned()),
+                ("a=b".to_owned(), "c=d".to_owned()),
+                ("\\".to_owned(), "/".to_owned()),
+            ]
+        );
+        assert!(cli().try_get_matches_from(["mg", "-S", "text", "-e", r"a\=b", "run"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn offset_round_trip() -> Result<()> {
+        assert_eq!(offset_from_env(Err(env::VarError::NotPresent))?, 0);
+        assert!(offset_from_env(Ok("not a number".to_owned())).is_err());
+        let assignment = offset_assignment("MG_OFFSET", 42);
+        assert_eq!(assignment, "MG_OFFSET=42");
+        let (_, value) = assignment.split_once('=').unwrap();
+        assert_eq!(offset_from_env(Ok(value.to_owned()))?, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn auto_window() {
+        assert_eq!(window_from_columns(Ok("80".to_owned())), 80);
+        assert_eq!(window_from_columns(Ok("0".to_owned())), DEFAULT_WINDOW);
+        assert_eq!(window_from_columns(Ok("wide".to_owned())), DEFAULT_WINDOW);
+        assert_eq!(window_from_columns(Err(env::VarError::NotPresent)), DEFAULT_WINDOW);
+        assert_eq!(window_from_terminal(Some(100), Ok("80".to_owned())), 100);
+        assert_eq!(window_from_terminal(None, Ok("80".to_owned())), 80);
+        assert_eq!(window_from_terminal(Some(0), Ok("80".to_owned())), 80);
+        assert_eq!(window_from_terminal(None, Err(env::VarError::NotPresent)), DEFAULT_WINDOW);
+    }
+
+    #[test]
+    fn keep_newlines() -> Result<()> {
+        let mut matches = cli().try_get_matches_from(["mg", "-S", "one\ntwo\r\n", "--keep-newlines", "run"])?;
+        let text = text_from_matches(&mut matches)?;
+        assert_eq!(text.get_raw_content(), "one\ntwo\n");
+        assert!(cli().try_get_matches_from(["mg", "-S", "text", "--keep-newlines", "-n", " ", "run"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn string_escapes() -> Result<()> {
+        let mut matches = cli().try_get_matches_from(["mg", "-S", r"a\tb\nc", "--string-escapes", "-n", " ", "run"])?;
+        assert_eq!(text_from_matches(&mut matches)?.get_raw_content(), "a\tb c");
+        let mut matches = cli().try_get_matches_from(["mg", "-S", r"a\tb", "run"])?;
+        assert_eq!(text_from_matches(&mut matches)?.get_raw_content(), r"a\tb");
+        Ok(())
+    }
+
+    #[test]
+    fn check_sources() -> Result<()> {
+        let mut matches = cli().try_get_matches_from(["mg", "-S", "text", "check"])?;
+        assert_eq!(check(&mut matches)?, "source: string");
+        let mut matches = cli().try_get_matches_from(["mg", "-f", "/nonexistent/mergneh", "check"])?;
+        assert!(check(&mut matches).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cmd_with_semicolon() -> Result<()> {
+        let mut matches = cli().try_get_matches_from(["mg", "--cmd", "echo", "a", r"\;", "b", ";", "-1", "run"])?;
+        let mut text = text_from_matches(&mut matches)?;
+        assert_eq!(text.next().unwrap()?, "a ; b");
+        Ok(())
+    }
+
+    #[test]
+    fn replacements_file() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-replacements-{}", process::id()));
+        fs::write(&path, "# pango\n&=&amp;\n\n<=&lt;\n")?;
+        let mut matches = cli().try_get_matches_from([
+            "mg",
+            "-S", "a & <b>",
+            "-1",
+            "--replacements-file", path.to_str().unwrap(),
+            "-e", ">=&gt;",
+            "run",
+        ])?;
+        let text = text_from_matches(&mut matches);
+        fs::remove_file(&path)?;
+        assert_eq!(text?.next().unwrap()?, "a &amp; &lt;b&gt;");
+        Ok(())
+    }
+
+    #[test]
+    fn source_required() -> Result<()> {
+        assert!(require_source(&mut cli(), &cli().try_get_matches_from(["mg", "-S", "text", "run"])?).is_ok());
+        #[cfg(feature = "mpd")]
+        {
+            let matches = cli().try_get_matches_from(["mg", "run"])?;
+            assert!(require_source(&mut cli(), &matches).is_err());
+            let matches = cli().try_get_matches_from(["mg", "format-check", "{title}"])?;
+            assert!(require_source(&mut cli(), &matches).is_ok());
+        }
+        #[cfg(not(feature = "mpd"))]
+        assert!(cli().try_get_matches_from(["mg", "run"]).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "mpd")]
+    #[test]
+    fn format_check_report() {
+        assert_eq!(
+            format_check("{artist} - {title} {{{elapsedTime:%S}}}").unwrap(),
+            "{artist} - {title} {{{elapsedTime:%S}}}\nplaceholders: {artist}, {title}, {elapsedTime:%S}"
+        );
+        for format in [
+            "{date:%d.%m} {totalTime:human} {elapsedBar:4} {stateIcon:1:2} {fileExtension:upper}",
+            "{'{raw}'} {{{crossfade:off}}} {basename:stem} {elapsedTime} {consumeIcon}",
+        ] {
+            let report = format_check(format).unwrap();
+            let (checked, _) = report.split_once('\n').unwrap();
+            assert_eq!(
+                checked.parse::<MpdFormatter>().unwrap(),
+                format.parse::<MpdFormatter>().unwrap()
+            );
+        }
+        assert_eq!(
+            format_check("ё {artist} - {titl}").unwrap_err(),
+            "Unknown placeholder 'titl' at column 15:\nё {artist} - {titl}\n              ^"
+        );
+        assert_eq!(
+            format_check("{album} }").unwrap_err(),
+            "Unmatched '{' or '}' at column 8:\n{album} }\n       ^"
+        );
+    }
+}
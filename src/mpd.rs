@@ -3,33 +3,36 @@ use std::{
     error::Error,
     fmt::Display,
     fmt::{self, Write},
-    net::SocketAddr,
+    io,
+    net::{SocketAddr, TcpStream},
     num::ParseIntError,
+    path::Path,
     str::FromStr,
     time::Duration,
 };
 
 use anyhow::Context;
 use chrono::{
-    format::{Item, StrftimeItems},
-    NaiveTime,
+    format::{DelayedFormat, Item, StrftimeItems},
+    NaiveDate, NaiveTime,
 };
+use clap::ValueEnum;
 use mpd::{song::QueuePlace, Client, Song, State, Status};
 
 use crate::text_source::ContentChange;
 
 #[derive(Debug)]
 pub enum IconSetParseError<const N: usize> {
-    NotEnoughChars,
-    TooManyChars,
+    NotEnoughIcons,
+    TooManyIcons,
+    EmptyIcon,
 }
 impl<const N: usize> Display for IconSetParseError<N> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            IconSetParseError::NotEnoughChars => {
-                write!(f, "Not enough characters (expected {})", N)
-            }
-            IconSetParseError::TooManyChars => write!(f, "Too many characters (expected {})", N),
+            IconSetParseError::NotEnoughIcons => write!(f, "Not enough icons (expected {})", N),
+            IconSetParseError::TooManyIcons => write!(f, "Too many icons (expected {})", N),
+            IconSetParseError::EmptyIcon => write!(f, "Icons must not be empty"),
         }
     }
 }
@@ -37,45 +40,145 @@ impl<const N: usize> Error for IconSetParseError<N> {}
 
 #[derive(Debug, Clone)]
 pub struct StateStatusIcons {
-    play: char,
-    pause: char,
-    stop: char,
+    play: String,
+    pause: String,
+    stop: String,
 }
 
 impl StateStatusIcons {
-    pub fn get_icon(&self, state: State) -> char {
+    pub fn get_icon(&self, state: State) -> &str {
         match state {
-            State::Stop => self.stop,
-            State::Play => self.play,
-            State::Pause => self.pause,
+            State::Stop => &self.stop,
+            State::Play => &self.play,
+            State::Pause => &self.pause,
         }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct StatusIcons {
-    enabled: char,
-    disabled: Option<char>,
+    enabled: String,
+    disabled: Option<String>,
 }
 
 impl StatusIcons {
-    pub fn get_icon(&self, state: bool) -> Option<char> {
+    pub fn get_icon(&self, state: bool) -> Option<&str> {
         if state {
-            Some(self.enabled)
+            Some(&self.enabled)
         } else {
-            self.disabled
+            self.disabled.as_deref()
         }
     }
 
-    pub fn write<T: Write>(&self, state: bool, pad: usize, f: &mut T) -> std::fmt::Result {
+    pub fn write<T: Write>(&self, state: bool, pad: Pad, f: &mut T) -> std::fmt::Result {
         if let Some(c) = self.get_icon(state) {
-            write!(f, "{}{}", c, " ".repeat(pad))
+            pad.write(c, f)
         } else {
             Ok(())
         }
     }
 }
 
+/// Spaces around an icon, specified as `left:right` or just `right`.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Pad {
+    left: usize,
+    right: usize,
+}
+
+impl Pad {
+    pub fn write<T: Write>(&self, icon: &str, f: &mut T) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            " ".repeat(self.left),
+            icon,
+            " ".repeat(self.right)
+        )
+    }
+}
+
+impl Display for Pad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.left {
+            0 => write!(f, "{}", self.right),
+            left => write!(f, "{}:{}", left, self.right),
+        }
+    }
+}
+
+impl From<usize> for Pad {
+    fn from(right: usize) -> Self {
+        Self { left: 0, right }
+    }
+}
+
+impl FromStr for Pad {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once(':') {
+            Some((left, right)) => Self {
+                left: left.parse()?,
+                right: right.parse()?,
+            },
+            None => Self::from(s.parse::<usize>()?),
+        })
+    }
+}
+
+/// Built-in set of icons, the icon options override its icons one by one.
+/// `ascii` is for terminals without a Nerd Font.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum IconTheme {
+    Nerdfont,
+    Ascii,
+    Emoji,
+}
+
+/// Icons of a theme, written the way the icon options take them with `,` as the separator.
+struct ThemeIcons {
+    state: &'static str,
+    consume: &'static str,
+    random: &'static str,
+    repeat: &'static str,
+    single: &'static str,
+}
+
+const NERDFONT_ICONS: ThemeIcons = ThemeIcons {
+    state: "\u{f04b},\u{f04c},\u{f04d}",
+    consume: "\u{f0c4}",
+    random: "\u{f074}",
+    repeat: "\u{f112}",
+    single: "\u{f01e}",
+};
+
+const ASCII_ICONS: ThemeIcons = ThemeIcons {
+    state: ">,||,[]",
+    consume: "c",
+    random: "z",
+    repeat: "r",
+    single: "1",
+};
+
+const EMOJI_ICONS: ThemeIcons = ThemeIcons {
+    state: "\u{25b6}\u{fe0f},\u{23f8}\u{fe0f},\u{23f9}\u{fe0f}",
+    consume: "\u{1f374}",
+    random: "\u{1f500}",
+    repeat: "\u{1f501}",
+    single: "\u{1f502}",
+};
+
+impl IconTheme {
+    fn icons(self) -> &'static ThemeIcons {
+        match self {
+            IconTheme::Nerdfont => &NERDFONT_ICONS,
+            IconTheme::Ascii => &ASCII_ICONS,
+            IconTheme::Emoji => &EMOJI_ICONS,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct StatusIconsSet {
     state: StateStatusIcons,
@@ -102,6 +205,32 @@ impl StatusIconsSet {
         }
     }
 
+    /// Icons of `theme` where the given ones are missing.
+    pub fn themed(
+        theme: IconTheme,
+        state_icons: Option<StateStatusIcons>,
+        consume_icons: Option<StatusIcons>,
+        random_icons: Option<StatusIcons>,
+        repeat_icons: Option<StatusIcons>,
+        single_icons: Option<StatusIcons>,
+    ) -> Self {
+        let icons = theme.icons();
+        let or_theme = |given: Option<StatusIcons>, theme: &str| {
+            given.unwrap_or_else(|| {
+                StatusIcons::parse(theme, Some(",")).expect("Theme icons must be valid")
+            })
+        };
+        Self::new(
+            state_icons.unwrap_or_else(|| {
+                StateStatusIcons::parse(icons.state, Some(",")).expect("Theme icons must be valid")
+            }),
+            or_theme(consume_icons, icons.consume),
+            or_theme(random_icons, icons.random),
+            or_theme(repeat_icons, icons.repeat),
+            or_theme(single_icons, icons.single),
+        )
+    }
+
     pub fn write_bool<T: Write>(&self, ph: &Placeholder, value: bool, f: &mut T) -> fmt::Result {
         match ph {
             Placeholder::ConsumeIcon(pad) => self.consume.write(value, *pad, f),
@@ -116,34 +245,92 @@ impl StatusIconsSet {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Placeholder {
     String(String),
+    Verbatim(String),
     Artist,
     AlbumArtist,
     Album,
     Title,
     Filename,
+    /// Last component of the file path, `false` drops the extension.
+    Basename(bool),
+    DisplayName,
+    FileExtension(LetterCase),
     Date,
-    TotalTime(Vec<Item<'static>>),
-    ElapsedTime(Vec<Item<'static>>),
+    /// The format as written and its parsed items.
+    FormattedDate(String, Vec<Item<'static>>),
+    TotalTime(DurationFormat),
+    ElapsedTime(DurationFormat),
+    ElapsedBar(usize),
     Volume,
+    /// Crossfade in seconds, the optional text replaces `0` when it is off.
+    Crossfade(Option<String>),
     SongPosition,
     QueueLength,
-    StateIcon(usize),
-    ConsumeIcon(usize),
-    RandomIcon(usize),
-    RepeatIcon(usize),
-    SingleIcon(usize),
+    QueueProgress,
+    StateIcon(Pad),
+    ConsumeIcon(Pad),
+    RandomIcon(Pad),
+    RepeatIcon(Pad),
+    SingleIcon(Pad),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum DurationFormat {
+    /// The format as written and its parsed items.
+    Strftime(String, Vec<Item<'static>>),
+    /// Like `3m 45s`, written with `humantime`.
+    Human,
+}
+
+impl DurationFormat {
+    fn parse(fmt: &str) -> Result<Self, MpdFormatParseError> {
+        if fmt == "human" {
+            return Ok(DurationFormat::Human);
+        }
+        Ok(DurationFormat::Strftime(
+            fmt.to_owned(),
+            StrftimeItems::new(fmt)
+                .parse_to_owned()
+                .map_err(MpdFormatParseError::DurationParseError)?,
+        ))
+    }
+
+    fn is_default(&self) -> bool {
+        matches!(self, DurationFormat::Strftime(spec, _) if spec == "%M:%S")
+    }
+}
+
+impl Display for DurationFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DurationFormat::Strftime(spec, _) => write!(f, "{}", spec),
+            DurationFormat::Human => write!(f, "human"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LetterCase {
+    AsIs,
+    Lower,
+    Upper,
 }
 
 #[derive(Debug, PartialEq)]
 pub enum PlaceholderValue<'a> {
     String(&'a str),
     OptionalString(Option<&'a str>),
+    OptionalExtension(Option<&'a str>, LetterCase),
+    OptionalDate(Option<&'a str>, &'a Vec<Item<'static>>),
     Volume(i8),
-    OptionalDuration(Option<Duration>, &'a Vec<Item<'static>>),
+    OptionalDuration(Option<Duration>, &'a DurationFormat),
     OptionalQueuePlace(Option<QueuePlace>),
     Len(u32),
+    QueueProgress(Option<QueuePlace>, u32),
+    Progress(Option<Duration>, Option<Duration>, usize),
     Bool(bool),
-    State(State, usize),
+    State(State, Pad),
+    Crossfade(Option<Duration>, Option<&'a str>),
 }
 
 impl Placeholder {
@@ -157,7 +344,7 @@ impl Placeholder {
             })
             .unwrap_or_default();
         match self {
-            Placeholder::String(s) => PlaceholderValue::String(s),
+            Placeholder::String(s) | Placeholder::Verbatim(s) => PlaceholderValue::String(s),
             Placeholder::Artist => PlaceholderValue::OptionalString(
                 song.map(|s| s.artist.as_deref()).unwrap_or_default(),
             ),
@@ -171,14 +358,36 @@ impl Placeholder {
             Placeholder::Filename => {
                 PlaceholderValue::OptionalString(song.map(|s| s.file.as_str()))
             }
+            Placeholder::Basename(extension) => {
+                PlaceholderValue::OptionalString(song.and_then(|s| basename(&s.file, *extension)))
+            }
+            Placeholder::DisplayName => PlaceholderValue::OptionalString(
+                song.and_then(|s| display_name(s.title.as_deref(), &s.file)),
+            ),
+            Placeholder::FileExtension(case) => PlaceholderValue::OptionalExtension(
+                song.and_then(|s| file_extension(&s.file)),
+                *case,
+            ),
             Placeholder::Date => PlaceholderValue::OptionalString(tags.remove("Date")),
+            Placeholder::FormattedDate(_, fmt) => {
+                PlaceholderValue::OptionalDate(tags.remove("Date"), fmt)
+            }
             Placeholder::Volume => PlaceholderValue::Volume(status.volume),
+            Placeholder::Crossfade(off) => {
+                PlaceholderValue::Crossfade(status.crossfade, off.as_deref())
+            }
             Placeholder::ElapsedTime(fmt) => {
-                PlaceholderValue::OptionalDuration(status.elapsed, fmt)
+                PlaceholderValue::OptionalDuration(elapsed(song.is_some(), status), fmt)
+            }
+            Placeholder::ElapsedBar(cells) => {
+                PlaceholderValue::Progress(elapsed(song.is_some(), status), status.duration, *cells)
             }
             Placeholder::TotalTime(fmt) => PlaceholderValue::OptionalDuration(status.duration, fmt),
             Placeholder::SongPosition => PlaceholderValue::OptionalQueuePlace(status.song),
             Placeholder::QueueLength => PlaceholderValue::Len(status.queue_len),
+            Placeholder::QueueProgress => {
+                PlaceholderValue::QueueProgress(status.song, status.queue_len)
+            }
             Placeholder::StateIcon(pad) => PlaceholderValue::State(status.state, *pad),
             Placeholder::ConsumeIcon(_) => PlaceholderValue::Bool(status.consume),
             Placeholder::RandomIcon(_) => PlaceholderValue::Bool(status.random),
@@ -188,7 +397,7 @@ impl Placeholder {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct MpdFormatter(Vec<Placeholder>);
 
 #[derive(Debug)]
@@ -197,6 +406,10 @@ pub enum MpdFormatParseError {
     RedundantFormat(String),
     DurationParseError(chrono::format::ParseError),
     PadParseError(ParseIntError),
+    WidthParseError(ParseIntError),
+    UnknownCase(String),
+    UnknownBasenameFormat(String),
+    NotDateFormat(String),
     UnmatchedParenthesis,
 }
 
@@ -213,45 +426,106 @@ impl Display for MpdFormatParseError {
                 write!(f, "Invalid duration format: {e}")
             }
             Self::PadParseError(e) => write!(f, "Padding parse error: {e}"),
-            Self::UnmatchedParenthesis => write!(f, "Unmatched '{{' or '}}"),
+            Self::WidthParseError(e) => write!(f, "Width parse error: {e}"),
+            Self::UnknownCase(case) => {
+                write!(f, "Unknown case '{case}' (expected 'lower' or 'upper')")
+            }
+            Self::UnknownBasenameFormat(format) => {
+                write!(f, "Unknown basename format '{format}' (expected 'stem')")
+            }
+            Self::NotDateFormat(format) => {
+                write!(f, "'{format}' has specifiers that do not apply to a date")
+            }
+            Self::UnmatchedParenthesis => write!(f, "Unmatched '{{' or '}}'"),
         }
     }
 }
 impl Error for MpdFormatParseError {}
 
+/// Stream that MPD is reached over, e.g. a tunnel or a TLS proxy instead of plain TCP.
+pub trait Connection: io::Read + io::Write + fmt::Debug {}
+
+impl<T: io::Read + io::Write + fmt::Debug> Connection for T {}
+
 #[derive(Debug)]
 pub struct MpdSource {
-    client: Client,
+    client: Client<Box<dyn Connection>>,
     current_song: Option<Song>,
     current_status: Status,
     running_format: MpdFormatter,
+    stopped_format: Option<MpdFormatter>,
     prefix_format: MpdFormatter,
     suffix_format: MpdFormatter,
     icons: StatusIconsSet,
     default_placeholder: String,
+    hide_when_stopped: bool,
+    escape_braces: bool,
 }
 
 impl MpdSource {
     pub fn new(
         addr: SocketAddr,
         fmt: MpdFormatter,
+        stopped_fmt: Option<MpdFormatter>,
+        prefix: MpdFormatter,
+        suffix: MpdFormatter,
+        icons: StatusIconsSet,
+        default_placeholder: String,
+    ) -> anyhow::Result<Self> {
+        Self::with_connector(
+            || Ok(Box::new(TcpStream::connect(addr)?)),
+            fmt,
+            stopped_fmt,
+            prefix,
+            suffix,
+            icons,
+            default_placeholder,
+        )
+    }
+    /// Like `new`, but talks to MPD over the stream returned by `connect`.
+    pub fn with_connector<F: FnOnce() -> io::Result<Box<dyn Connection>>>(
+        connect: F,
+        fmt: MpdFormatter,
+        stopped_fmt: Option<MpdFormatter>,
         prefix: MpdFormatter,
         suffix: MpdFormatter,
         icons: StatusIconsSet,
         default_placeholder: String,
     ) -> anyhow::Result<Self> {
-        let mut client = Client::connect(addr).context("MPD connection error")?;
+        let stream = connect().context("MPD connection error")?;
+        let mut client = Client::new(stream).context("MPD connection error")?;
         Ok(Self {
             current_song: client.currentsong().context("MPD server error")?,
             current_status: client.status().context("MPD server error")?,
             client,
             running_format: fmt,
+            stopped_format: stopped_fmt,
             prefix_format: prefix,
             suffix_format: suffix,
             icons,
             default_placeholder,
+            hide_when_stopped: false,
+            escape_braces: false,
         })
     }
+    /// Leaves the running text, the prefix and the suffix empty while MPD is stopped or has no song.
+    pub fn hide_when_stopped(mut self, enabled: bool) -> Self {
+        self.hide_when_stopped = enabled;
+        self
+    }
+    /// Doubles every brace in the output, so it can be parsed as a format again.
+    pub fn escape_braces(mut self, enabled: bool) -> Self {
+        self.escape_braces = enabled;
+        self
+    }
+    /// Whether everything is hidden in the current state.
+    pub fn is_hidden(&self) -> bool {
+        is_hidden(
+            self.hide_when_stopped,
+            self.current_song.is_some(),
+            self.current_status.state,
+        )
+    }
     pub fn get(
         &mut self,
         content: &mut String,
@@ -260,6 +534,18 @@ impl MpdSource {
     ) -> anyhow::Result<ContentChange> {
         let song = self.client.currentsong().context("MPD server error")?;
         let status = self.client.status().context("MPD server error")?;
+        let was_hidden = self.is_hidden();
+        if is_hidden(self.hide_when_stopped, song.is_some(), status.state) {
+            self.current_song = song;
+            self.current_status = status;
+            if was_hidden {
+                return Ok(ContentChange::empty());
+            }
+            content.clear();
+            prefix.clear();
+            suffix.clear();
+            return Ok(ContentChange::all());
+        }
         let mut change = ContentChange::empty();
         // I made this because I think this looks hilarious and I don't want to repeat this
         macro_rules! change {
@@ -269,7 +555,7 @@ impl MpdSource {
                 $(
                     change.set(
                         ContentChange::$type,
-                        self.$fmt
+                        was_hidden || self.$fmt
                         .iter()
                         .any(|ph| ph.get(self.current_song(), self.current_status()) != ph.get(song.as_ref(), &status)),
                     );
@@ -282,6 +568,7 @@ impl MpdSource {
                             song.as_ref(),
                             &status,
                             &self.default_placeholder,
+                            self.escape_braces,
                             $var,
                         )?;
                     }
@@ -291,7 +578,32 @@ impl MpdSource {
         change! {
             prefix if Prefix in prefix_format;
             suffix if Suffix in suffix_format;
-            content if Running in running_format;
+        }
+        let current_format = self.current_format();
+        let format = select_format(
+            &self.running_format,
+            self.stopped_format.as_ref(),
+            status.state,
+        );
+        change.set(
+            ContentChange::Running,
+            was_hidden
+                || !std::ptr::eq(current_format, format)
+                || format.iter().any(|ph| {
+                    ph.get(self.current_song(), self.current_status())
+                        != ph.get(song.as_ref(), &status)
+                }),
+        );
+        if change.contains(ContentChange::Running) {
+            content.clear();
+            format.format(
+                &self.icons,
+                song.as_ref(),
+                &status,
+                &self.default_placeholder,
+                self.escape_braces,
+                content,
+            )?;
         }
         self.current_song = song;
         self.current_status = status;
@@ -300,6 +612,17 @@ impl MpdSource {
     pub fn running_format(&self) -> &MpdFormatter {
         &self.running_format
     }
+    pub fn stopped_format(&self) -> Option<&MpdFormatter> {
+        self.stopped_format.as_ref()
+    }
+    /// Format of the running text for the current state.
+    pub fn current_format(&self) -> &MpdFormatter {
+        select_format(
+            &self.running_format,
+            self.stopped_format.as_ref(),
+            self.current_status.state,
+        )
+    }
     pub fn prefix_format(&self) -> &MpdFormatter {
         &self.prefix_format
     }
@@ -327,52 +650,76 @@ impl MpdFormatter {
             source.current_song(),
             source.current_status(),
             &source.default_placeholder,
+            source.escape_braces,
             f,
         )
     }
+    /// With `escape_braces` every brace is doubled, in the literal parts of the format as well
+    /// as in substituted values, so parsing the output gives back its text.
     pub fn format(
         &self,
         icons: &StatusIconsSet,
         song: Option<&Song>,
         status: &Status,
         default: &str,
+        escape_braces: bool,
         f: &mut String,
     ) -> anyhow::Result<()> {
         for ph in self.iter() {
+            let start = f.len();
             match ph.get(song, status) {
                 PlaceholderValue::String(s) => write!(f, "{}", s)?,
                 PlaceholderValue::OptionalString(s) => write!(f, "{}", s.unwrap_or(default))?,
+                PlaceholderValue::OptionalExtension(s, case) => match s {
+                    Some(ext) => write_with_case(ext, case, f)?,
+                    None => write!(f, "{}", default)?,
+                },
+                PlaceholderValue::OptionalDate(s, fmt) => match s {
+                    Some(date) => format_date(date, fmt, f)
+                        .map_err(|e| anyhow::anyhow!(e).context("Unsupported date specifier"))?,
+                    None => write!(f, "{}", default)?,
+                },
                 PlaceholderValue::Volume(v) => write!(f, "{}", v)?,
                 PlaceholderValue::Len(l) => write!(f, "{}", l)?,
-                PlaceholderValue::OptionalDuration(op, fmt) => match op {
-                    Some(d) => write!(
-                        f,
-                        "{}",
-                        chrono::format::DelayedFormat::new(
-                            None,
-                            NaiveTime::from_num_seconds_from_midnight_opt(
-                                d.as_secs() as _,
-                                d.subsec_nanos() as _
-                            ),
-                            fmt.iter()
-                        )
-                    )
-                    .map_err(|e| anyhow::anyhow!(e).context("Unsupported time specifier"))?,
-                    None => write!(f, "{}", default)?,
+                PlaceholderValue::OptionalDuration(op, fmt) => match (op, fmt) {
+                    (Some(d), DurationFormat::Strftime(_, fmt)) => format_duration(d, fmt, f)
+                        .map_err(|e| anyhow::anyhow!(e).context("Unsupported time specifier"))?,
+                    (Some(d), DurationFormat::Human) => format_human_duration(d, f)?,
+                    (None, _) => write!(f, "{}", default)?,
                 },
                 PlaceholderValue::OptionalQueuePlace(op) => match op {
                     Some(qp) => write!(f, "{}", qp.id),
                     None => write!(f, "{}", default),
                 }?,
-                PlaceholderValue::Bool(b) => icons.write_bool(ph, b, f)?,
-                PlaceholderValue::State(s, pad) => {
-                    write!(f, "{}{}", icons.state.get_icon(s), " ".repeat(pad))?
+                PlaceholderValue::QueueProgress(op, len) => {
+                    write_queue_progress(op.map(|qp| qp.pos), len, default, f)?
                 }
+                PlaceholderValue::Progress(elapsed, total, cells) => match (elapsed, total) {
+                    (Some(elapsed), Some(total)) if !total.is_zero() => {
+                        write_smooth_bar(elapsed.as_secs_f64() / total.as_secs_f64(), cells, f)?
+                    }
+                    _ => write!(f, "{}", default)?,
+                },
+                PlaceholderValue::Bool(b) => icons.write_bool(ph, b, f)?,
+                PlaceholderValue::State(s, pad) => pad.write(icons.state.get_icon(s), f)?,
+                PlaceholderValue::Crossfade(crossfade, off) => write_crossfade(crossfade, off, f)?,
             };
+            if escape_braces {
+                let value = f.split_off(start);
+                write_escaped_braces(&value, f)?;
+            }
         }
         Ok(())
     }
 
+    /// Placeholders the way they are written in a format, literal text is left out.
+    pub fn placeholder_specs(&self) -> Vec<String> {
+        self.iter()
+            .filter(|ph| !matches!(ph, Placeholder::String(_) | Placeholder::Verbatim(_)))
+            .map(Placeholder::to_string)
+            .collect()
+    }
+
     pub fn iter(&self) -> std::slice::Iter<'_, Placeholder> {
         self.0.iter()
     }
@@ -380,42 +727,71 @@ impl MpdFormatter {
 
 impl Display for MpdFormatter {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for ph in self.iter() {
-            if let Placeholder::String(s) = ph {
-                for part in s.split_inclusive(['{', '}']) {
-                    write!(f, "{}", part)?;
-                    match part.chars().last().expect("Part must not be empty") {
-                        c if matches!(c, '{' | '}') => write!(f, "{}", c)?,
-                        _ => continue,
-                    };
-                }
-            } else {
-                write!(
-                    f,
-                    "{}",
-                    match ph {
-                        Placeholder::Album => "{album}",
-                        Placeholder::AlbumArtist => "{albumArtist}",
-                        Placeholder::Artist => "{artist}",
-                        Placeholder::ConsumeIcon(_) => "{consumeIcon}",
-                        Placeholder::Date => "{date}",
-                        Placeholder::ElapsedTime(_) => "{elapsedTime}",
-                        Placeholder::Filename => "{filename}",
-                        Placeholder::QueueLength => "{queueLength}",
-                        Placeholder::RandomIcon(_) => "{randomIcon}",
-                        Placeholder::RepeatIcon(_) => "{repeatIcon}",
-                        Placeholder::SingleIcon(_) => "{singleIcon}",
-                        Placeholder::SongPosition => "{songPosition}",
-                        Placeholder::StateIcon(_) => "{stateIcon}",
-                        Placeholder::Title => "{title}",
-                        Placeholder::TotalTime(_) => "{totalTime}",
-                        Placeholder::Volume => "{volume}",
-                        Placeholder::String(_) => unreachable!(),
-                    }
-                )?;
+        self.iter().try_for_each(|ph| write!(f, "{}", ph))
+    }
+}
+
+impl Display for Placeholder {
+    /// Writes the placeholder the way it is written in a format, arguments that are
+    /// the defaults are left out and braces in literal text are doubled.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Placeholder::String(s) => write!(f, "{}", s.replace('{', "{{").replace('}', "}}")),
+            Placeholder::Verbatim(s) => write!(f, "{{'{}'}}", s),
+            Placeholder::Album => write!(f, "{{album}}"),
+            Placeholder::AlbumArtist => write!(f, "{{albumArtist}}"),
+            Placeholder::Artist => write!(f, "{{artist}}"),
+            Placeholder::Title => write!(f, "{{title}}"),
+            Placeholder::Filename => write!(f, "{{filename}}"),
+            Placeholder::Basename(true) => write!(f, "{{basename}}"),
+            Placeholder::Basename(false) => write!(f, "{{basename:stem}}"),
+            Placeholder::DisplayName => write!(f, "{{displayName}}"),
+            Placeholder::FileExtension(LetterCase::AsIs) => write!(f, "{{fileExtension}}"),
+            Placeholder::FileExtension(LetterCase::Lower) => write!(f, "{{fileExtension:lower}}"),
+            Placeholder::FileExtension(LetterCase::Upper) => write!(f, "{{fileExtension:upper}}"),
+            Placeholder::Date => write!(f, "{{date}}"),
+            Placeholder::FormattedDate(spec, _) => write!(f, "{{date:{}}}", spec),
+            Placeholder::TotalTime(fmt) => write_with_arg(f, "totalTime", fmt, !fmt.is_default()),
+            Placeholder::ElapsedTime(fmt) => {
+                write_with_arg(f, "elapsedTime", fmt, !fmt.is_default())
+            }
+            Placeholder::ElapsedBar(cells) => write_with_arg(f, "elapsedBar", cells, *cells != 10),
+            Placeholder::Volume => write!(f, "{{volume}}"),
+            Placeholder::Crossfade(None) => write!(f, "{{crossfade}}"),
+            Placeholder::Crossfade(Some(off)) => write!(f, "{{crossfade:{}}}", off),
+            Placeholder::SongPosition => write!(f, "{{songPosition}}"),
+            Placeholder::QueueLength => write!(f, "{{queueLength}}"),
+            Placeholder::QueueProgress => write!(f, "{{queueProgress}}"),
+            Placeholder::StateIcon(pad) => {
+                write_with_arg(f, "stateIcon", pad, *pad != Pad::default())
+            }
+            Placeholder::ConsumeIcon(pad) => {
+                write_with_arg(f, "consumeIcon", pad, *pad != Pad::default())
+            }
+            Placeholder::RandomIcon(pad) => {
+                write_with_arg(f, "randomIcon", pad, *pad != Pad::default())
+            }
+            Placeholder::RepeatIcon(pad) => {
+                write_with_arg(f, "repeatIcon", pad, *pad != Pad::default())
+            }
+            Placeholder::SingleIcon(pad) => {
+                write_with_arg(f, "singleIcon", pad, *pad != Pad::default())
             }
         }
-        Ok(())
+    }
+}
+
+/// Writes `{name:arg}`, or just `{name}` when the argument is not needed.
+fn write_with_arg(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    arg: impl Display,
+    needed: bool,
+) -> std::fmt::Result {
+    if needed {
+        write!(f, "{{{}:{}}}", name, arg)
+    } else {
+        write!(f, "{{{}}}", name)
     }
 }
 
@@ -423,132 +799,358 @@ impl FromStr for MpdFormatter {
     type Err = MpdFormatParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut placeholders = Vec::new();
-        let mut raw = String::new();
+        Self::parse_with_position(s).map_err(|(e, _)| e)
+    }
+}
+
+impl MpdFormatter {
+    /// Like `from_str`, but a failure also comes with the byte offset where parsing stopped:
+    /// the start of the offending placeholder or of the text before an unmatched brace.
+    pub fn parse_with_position(s: &str) -> Result<Self, (MpdFormatParseError, usize)> {
         let mut parse_slice = s;
-        while !parse_slice.is_empty() {
-            let left_par = match parse_slice.find(['{', '}']) {
-                Some(i) => i,
-                None => {
-                    raw.push_str(parse_slice);
-                    break;
+        parse_placeholders(&mut parse_slice)
+            .map(Self)
+            .map_err(|e| (e, s.len() - parse_slice.len()))
+    }
+}
+
+/// Parses placeholders from `parse_slice`, which is left where parsing stopped.
+fn parse_placeholders(parse_slice: &mut &str) -> Result<Vec<Placeholder>, MpdFormatParseError> {
+    let mut placeholders = Vec::new();
+    let mut raw = String::new();
+    while !parse_slice.is_empty() {
+        let left_par = match parse_slice.find(['{', '}']) {
+            Some(i) => i,
+            None => {
+                raw.push_str(parse_slice);
+                break;
+            }
+        };
+        if let Some('}') = &parse_slice[left_par..].chars().next() {
+            match parse_slice[left_par + 1..].chars().next() {
+                Some('}') => {
+                    raw.push_str(&parse_slice[..left_par + 1]);
+                    *parse_slice = &parse_slice[left_par + 2..];
+                    continue;
                 }
+                _ => return Err(MpdFormatParseError::UnmatchedParenthesis),
             };
-            if let Some('}') = &parse_slice[left_par..].chars().next() {
-                match parse_slice[left_par + 1..].chars().next() {
-                    Some('}') => {
-                        raw.push_str(&parse_slice[..left_par + 1]);
-                        parse_slice = &parse_slice[left_par + 2..];
-                        continue;
-                    }
-                    _ => return Err(MpdFormatParseError::UnmatchedParenthesis),
-                };
-            }
+        }
 
-            if let Some('{') = &parse_slice[left_par + 1..].chars().next() {
-                raw.push_str(&parse_slice[..left_par + 1]);
-                parse_slice = &parse_slice[left_par + 2..];
-                continue;
-            }
-            raw.push_str(&parse_slice[..left_par]);
-            parse_slice = &parse_slice[left_par + 1..];
-            if !raw.is_empty() {
-                placeholders.push(Placeholder::String(raw));
-                raw = String::new();
-            }
+        if let Some('{') = &parse_slice[left_par + 1..].chars().next() {
+            raw.push_str(&parse_slice[..left_par + 1]);
+            *parse_slice = &parse_slice[left_par + 2..];
+            continue;
+        }
+        raw.push_str(&parse_slice[..left_par]);
+        *parse_slice = &parse_slice[left_par + 1..];
+        if !raw.is_empty() {
+            placeholders.push(Placeholder::String(raw));
+            raw = String::new();
+        }
 
-            let right_par = match parse_slice.find(['{', '}']) {
-                Some(i) => i,
-                None => return Err(MpdFormatParseError::UnmatchedParenthesis),
-            };
-            if let Some('{') = parse_slice[right_par..].chars().next() {
-                return Err(MpdFormatParseError::UnmatchedParenthesis);
-            }
-            let ph_spec = &parse_slice[..right_par];
-            placeholders.push(if let Some((ph_type, ph_fmt)) = ph_spec.split_once(':') {
-                match ph_type {
-                    "date" => Placeholder::Date,
-                    "elapsedTime" => Placeholder::ElapsedTime(
-                        StrftimeItems::new(ph_fmt)
-                            .parse_to_owned()
-                            .map_err(MpdFormatParseError::DurationParseError)?,
-                    ),
-                    "totalTime" => Placeholder::TotalTime(
-                        StrftimeItems::new(ph_fmt)
-                            .parse_to_owned()
-                            .map_err(MpdFormatParseError::DurationParseError)?,
-                    ),
-                    "consumeIcon" | "repeatIcon" | "stateIcon" | "singleIcon" | "randomIcon" => {
-                        let pad = ph_fmt
-                            .parse::<usize>()
-                            .map_err(MpdFormatParseError::PadParseError)?;
-                        match ph_type {
-                            "consumeIcon" => Placeholder::ConsumeIcon(pad),
-                            "repeatIcon" => Placeholder::RepeatIcon(pad),
-                            "stateIcon" => Placeholder::StateIcon(pad),
-                            "singleIcon" => Placeholder::SingleIcon(pad),
-                            "randomIcon" => Placeholder::RandomIcon(pad),
-                            _ => unreachable!(),
-                        }
-                    }
-                    _ => return Err(MpdFormatParseError::RedundantFormat(ph_type.to_owned())),
-                }
-            } else {
-                match ph_spec {
-                    "album" => Placeholder::Album,
-                    "albumArtist" => Placeholder::AlbumArtist,
-                    "artist" => Placeholder::Artist,
-                    "consumeIcon" => Placeholder::ConsumeIcon(0),
-                    "date" => Placeholder::Date,
-                    "elapsedTime" => Placeholder::ElapsedTime(
-                        StrftimeItems::new("%M:%S").parse_to_owned().unwrap(),
-                    ),
-                    "filename" => Placeholder::Filename,
-                    "queueLength" => Placeholder::QueueLength,
-                    "randomIcon" => Placeholder::RandomIcon(0),
-                    "repeatIcon" => Placeholder::RepeatIcon(0),
-                    "singleIcon" => Placeholder::SingleIcon(0),
-                    "songPosition" => Placeholder::SongPosition,
-                    "stateIcon" => Placeholder::StateIcon(0),
-                    "title" => Placeholder::Title,
-                    "totalTime" => Placeholder::TotalTime(
-                        StrftimeItems::new("%M:%S").parse_to_owned().unwrap(),
-                    ),
-                    "volume" => Placeholder::Volume,
+        if let Some(verbatim) = parse_slice.strip_prefix('\'') {
+            let end = verbatim
+                .find("'}")
+                .ok_or(MpdFormatParseError::UnmatchedParenthesis)?;
+            placeholders.push(Placeholder::Verbatim(verbatim[..end].to_owned()));
+            *parse_slice = &verbatim[end + 2..];
+            continue;
+        }
+
+        let right_par = match parse_slice.find(['{', '}']) {
+            Some(i) => i,
+            None => return Err(MpdFormatParseError::UnmatchedParenthesis),
+        };
+        if let Some('{') = parse_slice[right_par..].chars().next() {
+            return Err(MpdFormatParseError::UnmatchedParenthesis);
+        }
+        let ph_spec = &parse_slice[..right_par];
+        placeholders.push(if let Some((ph_type, ph_fmt)) = ph_spec.split_once(':') {
+            match ph_type {
+                "date" => Placeholder::FormattedDate(ph_fmt.to_owned(), parse_date_format(ph_fmt)?),
+                "elapsedTime" => Placeholder::ElapsedTime(DurationFormat::parse(ph_fmt)?),
+                "totalTime" => Placeholder::TotalTime(DurationFormat::parse(ph_fmt)?),
+                "fileExtension" => Placeholder::FileExtension(match ph_fmt {
+                    "lower" => LetterCase::Lower,
+                    "upper" => LetterCase::Upper,
+                    _ => return Err(MpdFormatParseError::UnknownCase(ph_fmt.to_owned())),
+                }),
+                "crossfade" => Placeholder::Crossfade(Some(ph_fmt.to_owned())),
+                "basename" => match ph_fmt {
+                    "stem" => Placeholder::Basename(false),
                     _ => {
-                        return Err(MpdFormatParseError::UnknownPlaceholder(
-                            parse_slice[..right_par].to_owned(),
+                        return Err(MpdFormatParseError::UnknownBasenameFormat(
+                            ph_fmt.to_owned(),
                         ))
                     }
+                },
+                "elapsedBar" => Placeholder::ElapsedBar(
+                    ph_fmt
+                        .parse::<usize>()
+                        .map_err(MpdFormatParseError::WidthParseError)?,
+                ),
+                "consumeIcon" | "repeatIcon" | "stateIcon" | "singleIcon" | "randomIcon" => {
+                    let pad = ph_fmt
+                        .parse::<Pad>()
+                        .map_err(MpdFormatParseError::PadParseError)?;
+                    match ph_type {
+                        "consumeIcon" => Placeholder::ConsumeIcon(pad),
+                        "repeatIcon" => Placeholder::RepeatIcon(pad),
+                        "stateIcon" => Placeholder::StateIcon(pad),
+                        "singleIcon" => Placeholder::SingleIcon(pad),
+                        "randomIcon" => Placeholder::RandomIcon(pad),
+                        _ => unreachable!(),
+                    }
                 }
-            });
-            parse_slice = &parse_slice[right_par + 1..];
-        }
-        if !raw.is_empty() {
-            placeholders.push(Placeholder::String(raw));
-        }
-        Ok(Self(placeholders))
+                _ => return Err(MpdFormatParseError::RedundantFormat(ph_type.to_owned())),
+            }
+        } else {
+            match ph_spec {
+                "album" => Placeholder::Album,
+                "albumArtist" => Placeholder::AlbumArtist,
+                "artist" => Placeholder::Artist,
+                "consumeIcon" => Placeholder::ConsumeIcon(Pad::default()),
+                "date" => Placeholder::Date,
+                "displayName" => Placeholder::DisplayName,
+                "elapsedTime" => Placeholder::ElapsedTime(DurationFormat::parse("%M:%S").unwrap()),
+                "elapsedBar" => Placeholder::ElapsedBar(10),
+                "filename" => Placeholder::Filename,
+                "basename" => Placeholder::Basename(true),
+                "fileExtension" => Placeholder::FileExtension(LetterCase::AsIs),
+                "queueLength" => Placeholder::QueueLength,
+                "queueProgress" => Placeholder::QueueProgress,
+                "randomIcon" => Placeholder::RandomIcon(Pad::default()),
+                "repeatIcon" => Placeholder::RepeatIcon(Pad::default()),
+                "singleIcon" => Placeholder::SingleIcon(Pad::default()),
+                "songPosition" => Placeholder::SongPosition,
+                "stateIcon" => Placeholder::StateIcon(Pad::default()),
+                "title" => Placeholder::Title,
+                "totalTime" => Placeholder::TotalTime(DurationFormat::parse("%M:%S").unwrap()),
+                "volume" => Placeholder::Volume,
+                "crossfade" => Placeholder::Crossfade(None),
+                _ => {
+                    return Err(MpdFormatParseError::UnknownPlaceholder(
+                        parse_slice[..right_par].to_owned(),
+                    ))
+                }
+            }
+        });
+        *parse_slice = &parse_slice[right_par + 1..];
+    }
+    if !raw.is_empty() {
+        placeholders.push(Placeholder::String(raw));
+    }
+    Ok(placeholders)
+}
+
+/// Formats a duration as a time of day, subsecond specifiers like `%.3f` are kept precise.
+fn format_duration<T: Write>(d: Duration, fmt: &[Item<'static>], f: &mut T) -> fmt::Result {
+    let time = NaiveTime::from_num_seconds_from_midnight_opt(d.as_secs() as _, d.subsec_nanos());
+    write!(f, "{}", DelayedFormat::new(None, time, fmt.iter()))
+}
+
+/// Formats a duration like `1h 2m 5s`, fractions of a second are dropped.
+fn format_human_duration<T: Write>(d: Duration, f: &mut T) -> fmt::Result {
+    write!(
+        f,
+        "{}",
+        humantime::format_duration(Duration::from_secs(d.as_secs()))
+    )
+}
+
+/// Parses the format of `{date:...}`, rejecting specifiers a date alone cannot fill in, like `%H`.
+fn parse_date_format(fmt: &str) -> Result<Vec<Item<'static>>, MpdFormatParseError> {
+    let items = StrftimeItems::new(fmt)
+        .parse_to_owned()
+        .map_err(MpdFormatParseError::DurationParseError)?;
+    let mut probe = String::new();
+    write!(
+        probe,
+        "{}",
+        DelayedFormat::new(Some(NaiveDate::MIN), None, items.iter())
+    )
+    .map_err(|_| MpdFormatParseError::NotDateFormat(fmt.to_owned()))?;
+    Ok(items)
+}
+
+/// Reformats a `Date` tag which is usually `YYYY`, `YYYY-MM` or `YYYY-MM-DD`,
+/// tags in other formats are written as is.
+fn format_date<T: Write>(date: &str, fmt: &[Item<'static>], f: &mut T) -> fmt::Result {
+    let parsed = [
+        date.to_owned(),
+        format!("{date}-01"),
+        format!("{date}-01-01"),
+    ]
+    .iter()
+    .find_map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+    match parsed {
+        Some(d) => write!(f, "{}", DelayedFormat::new(Some(d), None, fmt.iter())),
+        None => write!(f, "{}", date),
+    }
+}
+
+/// Eighth blocks from the thinnest to the full one.
+const BLOCKS: [char; 8] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉', '█'];
+
+/// Writes a bar of `cells` characters filled up to `ratio` with a resolution of an eighth of a cell.
+fn write_smooth_bar<T: Write>(ratio: f64, cells: usize, f: &mut T) -> fmt::Result {
+    let eighths = (ratio.clamp(0.0, 1.0) * cells as f64 * 8.0).round() as usize;
+    let (full, partial) = (eighths / 8, eighths % 8);
+    for _ in 0..full {
+        f.write_char(BLOCKS[7])?;
+    }
+    if partial > 0 {
+        f.write_char(BLOCKS[partial - 1])?;
+    }
+    for _ in full + usize::from(partial > 0)..cells {
+        f.write_char(' ')?;
+    }
+    Ok(())
+}
+
+/// Writes a 0-based queue position as `position/length` counting from 1.
+fn write_queue_progress<T: Write>(
+    pos: Option<u32>,
+    len: u32,
+    default: &str,
+    f: &mut T,
+) -> fmt::Result {
+    match pos {
+        Some(pos) => write!(f, "{}/{}", pos + 1, len),
+        None => write!(f, "{}/{}", default, len),
+    }
+}
+
+/// The stopped format takes over the running one when MPD is stopped.
+fn select_format<'a>(
+    running: &'a MpdFormatter,
+    stopped: Option<&'a MpdFormatter>,
+    state: State,
+) -> &'a MpdFormatter {
+    match (stopped, state) {
+        (Some(stopped), State::Stop) => stopped,
+        _ => running,
+    }
+}
+
+fn is_hidden(hide_when_stopped: bool, has_song: bool, state: State) -> bool {
+    hide_when_stopped && (state == State::Stop || !has_song)
+}
+
+/// Elapsed time of the current song, a song that is paused or playing is at zero
+/// if MPD does not report it, e.g. when it is paused before playback started.
+fn elapsed(has_song: bool, status: &Status) -> Option<Duration> {
+    match status.state {
+        State::Play | State::Pause if has_song => status.elapsed.or(Some(Duration::ZERO)),
+        _ => status.elapsed,
     }
 }
 
-macro_rules! next_or_err {
-    ($iter:ident => $type:ident: $($field:ident),+) => {
-        $type {
-            $($field: $iter.next().ok_or(IconSetParseError::NotEnoughChars)?),+
+/// Whole seconds of crossfade, `off` is written instead of `0` if given.
+fn write_crossfade<T: Write>(
+    crossfade: Option<Duration>,
+    off: Option<&str>,
+    f: &mut T,
+) -> fmt::Result {
+    match crossfade.map(|d| d.as_secs()).unwrap_or_default() {
+        0 => write!(f, "{}", off.unwrap_or("0")),
+        secs => write!(f, "{}", secs),
+    }
+}
+
+/// Writes `s` with every brace doubled, the way literal braces are written in a format.
+fn write_escaped_braces<T: Write>(s: &str, f: &mut T) -> fmt::Result {
+    for part in s.split_inclusive(['{', '}']) {
+        f.write_str(part)?;
+        if part.ends_with(['{', '}']) {
+            f.write_str(&part[part.len() - 1..])?;
         }
+    }
+    Ok(())
+}
+
+/// The file name without directories, `extension` keeps its extension.
+fn basename(file: &str, extension: bool) -> Option<&str> {
+    let path = Path::new(file);
+    if extension {
+        path.file_name()
+    } else {
+        path.file_stem()
+    }
+    .and_then(|name| name.to_str())
+    .filter(|name| !name.is_empty())
+}
+
+/// The title if it is not empty, otherwise the file name without directories.
+fn display_name<'a>(title: Option<&'a str>, file: &'a str) -> Option<&'a str> {
+    title
+        .filter(|title| !title.is_empty())
+        .or_else(|| basename(file, true))
+}
+
+/// Extension of a song file, `None` if there is none.
+fn file_extension(file: &str) -> Option<&str> {
+    Path::new(file).extension().and_then(|ext| ext.to_str())
+}
+
+fn write_with_case<T: Write>(s: &str, case: LetterCase, f: &mut T) -> fmt::Result {
+    match case {
+        LetterCase::AsIs => write!(f, "{}", s),
+        LetterCase::Lower => write!(f, "{}", s.to_lowercase()),
+        LetterCase::Upper => write!(f, "{}", s.to_uppercase()),
+    }
+}
+
+/// Splits icons at `separator`, without one every character is an icon.
+/// Icons are checked to be at most `N` and not empty.
+fn split_icons<const N: usize>(
+    s: &str,
+    separator: Option<&str>,
+) -> Result<Vec<String>, IconSetParseError<N>> {
+    let icons = match separator {
+        Some(separator) => s.split(separator).map(str::to_owned).collect::<Vec<_>>(),
+        None => s.chars().map(String::from).collect(),
     };
+    if icons.iter().any(String::is_empty) {
+        return Err(IconSetParseError::EmptyIcon);
+    }
+    if icons.len() > N {
+        return Err(IconSetParseError::TooManyIcons);
+    }
+    Ok(icons)
+}
+
+impl StateStatusIcons {
+    /// Parses play, pause and stop icons separated by `separator`, one character each without it.
+    pub fn parse(s: &str, separator: Option<&str>) -> Result<Self, IconSetParseError<3>> {
+        let mut icons = split_icons(s, separator)?.into_iter();
+        let mut next = || icons.next().ok_or(IconSetParseError::NotEnoughIcons);
+        Ok(StateStatusIcons {
+            play: next()?,
+            pause: next()?,
+            stop: next()?,
+        })
+    }
 }
 
 impl FromStr for StateStatusIcons {
     type Err = IconSetParseError<3>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut iter = s.chars();
-        let result = Ok(next_or_err!(iter => StateStatusIcons: play, pause, stop));
-        if iter.next().is_some() {
-            return Err(IconSetParseError::TooManyChars);
-        }
-        result
+        Self::parse(s, None)
+    }
+}
+
+impl StatusIcons {
+    /// Parses the enabled and the optional disabled icon separated by `separator`,
+    /// one character each without it.
+    pub fn parse(s: &str, separator: Option<&str>) -> Result<Self, IconSetParseError<2>> {
+        let mut icons = split_icons(s, separator)?.into_iter();
+        Ok(StatusIcons {
+            enabled: icons.next().ok_or(IconSetParseError::NotEnoughIcons)?,
+            disabled: icons.next(),
+        })
     }
 }
 
@@ -556,31 +1158,44 @@ impl FromStr for StatusIcons {
     type Err = IconSetParseError<2>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut iter = s.chars();
-        let result = Ok(StatusIcons {
-            enabled: iter.next().ok_or(IconSetParseError::NotEnoughChars)?,
-            disabled: iter.next(),
-        });
-        if iter.next().is_some() {
-            return Err(IconSetParseError::TooManyChars);
-        }
-        result
+        Self::parse(s, None)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::mpd::{MpdFormatParseError, MpdFormatter, Placeholder};
+    use crate::mpd::{
+        basename, display_name, file_extension, format_date, format_duration,
+        format_human_duration, is_hidden, select_format, write_crossfade, write_queue_progress,
+        write_smooth_bar, write_with_case, DurationFormat, IconSetParseError, IconTheme,
+        LetterCase, MpdFormatParseError, MpdFormatter, Pad, Placeholder, StateStatusIcons,
+        StatusIcons, StatusIconsSet,
+    };
     use chrono::format::StrftimeItems;
+    use clap::ValueEnum;
+    use mpd::{Song, State, Status};
+    use std::time::Duration;
     macro_rules! ph {
         ($p:ident) => {
             Placeholder::$p
         };
+        ($p:ident(+$v:literal)) => {
+            Placeholder::$p(Pad::from($v))
+        };
         ($p:ident(#$v:literal)) => {
             Placeholder::$p($v)
         };
         ($p:ident(*$v:literal)) => {
-            Placeholder::$p(StrftimeItems::new($v).parse_to_owned().unwrap())
+            Placeholder::$p(
+                $v.to_owned(),
+                StrftimeItems::new($v).parse_to_owned().unwrap(),
+            )
+        };
+        ($p:ident(%$v:literal)) => {
+            Placeholder::$p(DurationFormat::Strftime(
+                $v.to_owned(),
+                StrftimeItems::new($v).parse_to_owned().unwrap(),
+            ))
         };
         ($str:literal) => {
             Placeholder::String($str.to_owned())
@@ -601,12 +1216,45 @@ mod tests {
         assert_ok!("rawstr" => ["rawstr"]);
         assert_ok!("" => []);
         assert_ok!("{artist} - {title}" => [Artist, " - ", Title]);
-        assert_ok!(" [{elapsedTime}/{totalTime}] {stateIcon}" => [" [", ElapsedTime(*"%M:%S"), "/", TotalTime(*"%M:%S"), "] ", StateIcon(#0)]);
+        assert_ok!(" [{elapsedTime}/{totalTime}] {stateIcon}" => [" [", ElapsedTime(%"%M:%S"), "/", TotalTime(%"%M:%S"), "] ", StateIcon(+0)]);
         assert_ok!(
             " [{elapsedTime:%M with %S}/{totalTime:%H hours %M minutes %S seconds}] {stateIcon:1}"
-            => [" [", ElapsedTime(*"%M with %S"), "/", TotalTime(*"%H hours %M minutes %S seconds"), "] ", StateIcon(#1)]
+            => [" [", ElapsedTime(%"%M with %S"), "/", TotalTime(%"%H hours %M minutes %S seconds"), "] ", StateIcon(+1)]
+        );
+        assert_ok!("{date:%d.%m.%Y}" => [FormattedDate(*"%d.%m.%Y")]);
+        assert_eq!(
+            "{fileExtension} {fileExtension:upper}"
+                .parse::<MpdFormatter>()
+                .unwrap()
+                .0,
+            vec![
+                Placeholder::FileExtension(LetterCase::AsIs),
+                ph!(" "),
+                Placeholder::FileExtension(LetterCase::Upper)
+            ]
         );
         assert_ok!("{{}}" => ["{}"]);
+        assert_ok!("[{queueProgress}]" => ["[", QueueProgress, "]"]);
+        assert_ok!("{displayName}" => [DisplayName]);
+        assert_eq!(
+            "{consumeIcon:1:0}{randomIcon:2}"
+                .parse::<MpdFormatter>()
+                .unwrap()
+                .0,
+            vec![
+                Placeholder::ConsumeIcon(Pad { left: 1, right: 0 }),
+                Placeholder::RandomIcon(Pad { left: 0, right: 2 })
+            ]
+        );
+        assert!(matches!(
+            "{stateIcon:1:}".parse::<MpdFormatter>().unwrap_err(),
+            MpdFormatParseError::PadParseError(_)
+        ));
+        assert_ok!("{elapsedBar}{elapsedBar:4}" => [ElapsedBar(#10), ElapsedBar(#4)]);
+        assert_eq!(
+            "{'{a} }}{'}{title}".parse::<MpdFormatter>().unwrap().0,
+            vec![Placeholder::Verbatim("{a} }}{".to_owned()), ph!(Title)]
+        );
         assert_ok!("{{{artist}}}" => ["{", Artist, "}"]);
         assert_ok!("{{{artist}{title}}}" => ["{", Artist, Title, "}"]);
         assert_ok!("{artist} {title}}}" => [Artist, " ", Title, "}"]);
@@ -615,6 +1263,17 @@ mod tests {
         assert_ok!("{artist}{title}" => ["", Artist, "", Title, ""]);
         assert_ok!("}}{{{artist}}}{title}}}" => ["}{", Artist, "}", Title, "}"]);
         assert_err!("{artst}" => UnknownPlaceholder("artst"));
+        assert_err!("{fileExtension:title}" => UnknownCase("title"));
+        assert_err!("{date:%H:%M}" => NotDateFormat("%H:%M"));
+        assert_err!("{date:%Y %s}" => NotDateFormat("%Y %s"));
+        assert!(matches!(
+            "{date:%Q}".parse::<MpdFormatter>().unwrap_err(),
+            MpdFormatParseError::DurationParseError(_)
+        ));
+        assert!(matches!(
+            "{elapsedBar:wide}".parse::<MpdFormatter>().unwrap_err(),
+            MpdFormatParseError::WidthParseError(_)
+        ));
         assert_err!("{}artist}}" => UnknownPlaceholder(""));
         assert_err!("{ar}tst}" => UnknownPlaceholder("ar"));
         assert_err!("{artist}}" => UnmatchedParenthesis);
@@ -624,6 +1283,7 @@ mod tests {
         assert_err!("{{{{artist}}}" => UnmatchedParenthesis);
         assert_err!("{{{artist}}}{" => UnmatchedParenthesis);
         assert_err!("{{{artist}}}}" => UnmatchedParenthesis);
+        assert_err!("{'{artist}" => UnmatchedParenthesis);
     }
 
     #[test]
@@ -649,6 +1309,15 @@ mod tests {
         assert!("rawstr");
         assert!("");
         assert!("{artist} - {title}");
+        assert!("{fileExtension}");
+        assert!("{fileExtension:upper} {fileExtension:lower}");
+        assert!("{date} {date:%d.%m.%Y}");
+        assert!("{displayName}");
+        assert!("{elapsedBar} {elapsedBar:4}");
+        assert!("{elapsedTime}/{totalTime} {elapsedTime:%S} {totalTime:human}");
+        assert!("{consumeIcon:1:0}{randomIcon:2} {stateIcon}");
+        assert!("{queueProgress} {songPosition}/{queueLength}");
+        assert!("{artist}{'{ raw }} text'} {{}}");
         assert!("{{}}");
         assert!("{{{artist}}}");
         assert!("{{{artist}{title}}}");
@@ -658,4 +1327,385 @@ mod tests {
         assert!("{artist}{title}");
         assert!("}}{{{artist}}}{title}}}");
     }
+
+    #[test]
+    fn date_format_test() {
+        macro_rules! assert {
+            ($date:literal, $fmt:literal => $str:literal) => {
+                let mut f = String::new();
+                format_date(
+                    $date,
+                    &StrftimeItems::new($fmt).parse_to_owned().unwrap(),
+                    &mut f,
+                )
+                .unwrap();
+                assert_eq!(f, $str)
+            };
+        }
+        assert!("2021-03-04", "%d.%m.%Y" => "04.03.2021");
+        assert!("2021-03", "%B %Y" => "March 2021");
+        assert!("2021", "%y" => "21");
+        assert!("spring of '21", "%Y" => "spring of '21");
+    }
+
+    #[test]
+    fn file_extension_test() {
+        assert_eq!(file_extension("music/song.flac"), Some("flac"));
+        assert_eq!(file_extension("music/song"), None);
+        assert_eq!(file_extension("http://radio.example/stream"), None);
+        let mut f = String::new();
+        write_with_case("flac", LetterCase::AsIs, &mut f).unwrap();
+        write_with_case("Opus", LetterCase::Upper, &mut f).unwrap();
+        write_with_case("MP3", LetterCase::Lower, &mut f).unwrap();
+        assert_eq!(f, "flacOPUSmp3");
+    }
+
+    #[test]
+    fn hide_when_stopped_test() {
+        assert!(is_hidden(true, true, State::Stop));
+        assert!(is_hidden(true, false, State::Play));
+        assert!(!is_hidden(true, true, State::Pause));
+        assert!(!is_hidden(false, false, State::Stop));
+    }
+
+    #[test]
+    fn paused_at_start_test() {
+        let format = "{elapsedTime}".parse::<MpdFormatter>().unwrap();
+        let icons = StatusIconsSet::themed(IconTheme::Ascii, None, None, None, None, None);
+        let song = Song::default();
+        let elapsed = |song: Option<&Song>, state, elapsed| {
+            let status = Status {
+                state,
+                elapsed,
+                ..Default::default()
+            };
+            let mut f = String::new();
+            format
+                .format(&icons, song, &status, "N/A", false, &mut f)
+                .unwrap();
+            f
+        };
+        assert_eq!(
+            elapsed(Some(&song), State::Pause, Some(Duration::ZERO)),
+            "00:00"
+        );
+        assert_eq!(elapsed(Some(&song), State::Pause, None), "00:00");
+        assert_eq!(elapsed(Some(&song), State::Play, None), "00:00");
+        assert_eq!(elapsed(Some(&song), State::Stop, None), "N/A");
+        assert_eq!(elapsed(None, State::Pause, None), "N/A");
+        assert_eq!(
+            elapsed(Some(&song), State::Pause, Some(Duration::from_secs(75))),
+            "01:15"
+        );
+    }
+
+    #[test]
+    fn stopped_format_test() {
+        let running = "{artist} - {title}".parse::<MpdFormatter>().unwrap();
+        let stopped = "stopped".parse::<MpdFormatter>().unwrap();
+        assert_eq!(
+            select_format(&running, Some(&stopped), State::Stop).to_string(),
+            "stopped"
+        );
+        for state in [State::Play, State::Pause] {
+            assert_eq!(
+                select_format(&running, Some(&stopped), state).to_string(),
+                "{artist} - {title}"
+            );
+        }
+        assert_eq!(
+            select_format(&running, None, State::Stop).to_string(),
+            "{artist} - {title}"
+        );
+    }
+
+    #[test]
+    fn duration_format_test() {
+        macro_rules! assert {
+            ($secs:literal, $fmt:literal => $str:literal) => {
+                let mut f = String::new();
+                format_duration(
+                    Duration::from_secs_f64($secs),
+                    &StrftimeItems::new($fmt).parse_to_owned().unwrap(),
+                    &mut f,
+                )
+                .unwrap();
+                assert_eq!(f, $str)
+            };
+        }
+        assert!(65.5, "%M:%S" => "01:05");
+        assert!(65.5, "%M:%S%.3f" => "01:05.500");
+        assert!(65.25, "%M:%S%.f" => "01:05.250");
+        assert!(3725.0, "%H:%M:%S" => "01:02:05");
+    }
+
+    #[test]
+    fn human_duration_test() {
+        let mut f = String::new();
+        for secs in [0.0, 45.75, 225.0, 3720.0, 3725.5] {
+            format_human_duration(Duration::from_secs_f64(secs), &mut f).unwrap();
+            f.push('|');
+        }
+        assert_eq!(f, "0s|45s|3m 45s|1h 2m|1h 2m 5s|");
+        assert_eq!(
+            "{totalTime:human}".parse::<MpdFormatter>().unwrap().0,
+            vec![Placeholder::TotalTime(DurationFormat::Human)]
+        );
+    }
+
+    #[test]
+    fn queue_progress_test() {
+        let mut f = String::new();
+        write_queue_progress(Some(2), 17, "N/A", &mut f).unwrap();
+        assert_eq!(f, "3/17");
+        f.clear();
+        write_queue_progress(None, 17, "N/A", &mut f).unwrap();
+        assert_eq!(f, "N/A/17");
+    }
+
+    #[test]
+    fn smooth_bar_test() {
+        macro_rules! assert {
+            ($ratio:literal, $cells:literal => $str:literal) => {
+                let mut f = String::new();
+                write_smooth_bar($ratio, $cells, &mut f).unwrap();
+                assert_eq!(f, $str)
+            };
+        }
+        assert!(0.0, 10 => "          ");
+        assert!(0.37, 10 => "███▊      ");
+        assert!(0.5, 4 => "██  ");
+        assert!(0.01, 10 => "▏         ");
+        assert!(1.0, 10 => "██████████");
+        assert!(1.5, 3 => "███");
+    }
+
+    #[test]
+    fn crossfade_test() {
+        let mut f = String::new();
+        write_crossfade(Some(Duration::from_secs(5)), Some("off"), &mut f).unwrap();
+        write_crossfade(Some(Duration::ZERO), Some("off"), &mut f).unwrap();
+        write_crossfade(None, Some("off"), &mut f).unwrap();
+        write_crossfade(Some(Duration::ZERO), None, &mut f).unwrap();
+        write_crossfade(Some(Duration::from_secs(12)), None, &mut f).unwrap();
+        assert_eq!(f, "5offoff012");
+        let format = "xfade: {crossfade}, {crossfade:no crossfade}";
+        assert_eq!(
+            format.parse::<MpdFormatter>().unwrap().0,
+            vec![
+                ph!("xfade: "),
+                Placeholder::Crossfade(None),
+                ph!(", "),
+                Placeholder::Crossfade(Some("no crossfade".to_owned()))
+            ]
+        );
+        assert_eq!(format.parse::<MpdFormatter>().unwrap().to_string(), format);
+    }
+
+    #[test]
+    fn icon_theme_test() {
+        for theme in IconTheme::value_variants() {
+            StatusIconsSet::themed(*theme, None, None, None, None, None);
+        }
+        let icons = StatusIconsSet::themed(
+            IconTheme::Ascii,
+            None,
+            None,
+            Some("R-".parse().unwrap()),
+            None,
+            None,
+        );
+        assert_eq!(icons.state.get_icon(State::Play), ">");
+        assert_eq!(icons.state.get_icon(State::Pause), "||");
+        assert_eq!(icons.state.get_icon(State::Stop), "[]");
+        assert_eq!(icons.consume.get_icon(true), Some("c"));
+        assert_eq!(icons.random.get_icon(true), Some("R"));
+        assert_eq!(icons.random.get_icon(false), Some("-"));
+        assert_eq!(icons.repeat.get_icon(true), Some("r"));
+        assert_eq!(icons.single.get_icon(true), Some("1"));
+        assert_eq!(icons.single.get_icon(false), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn custom_connection() -> anyhow::Result<()> {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            os::unix::net::UnixStream,
+            thread,
+        };
+
+        use super::{Connection, MpdSource};
+        use crate::text_source::ContentChange;
+
+        let (client, server) = UnixStream::pair()?;
+        let mpd = thread::spawn(move || -> std::io::Result<()> {
+            let mut writer = server.try_clone()?;
+            writer.write_all(b"OK MPD 0.23.5\n")?;
+            let mut titles = ["One", "Two"].into_iter().cycle();
+            let mut title = titles.next().unwrap();
+            for command in BufReader::new(server).lines() {
+                match command?.as_str() {
+                    "currentsong" => write!(
+                        writer,
+                        "file: music/song.flac\nTitle: {title}\nPos: 0\nId: 1\nOK\n"
+                    )?,
+                    "command_list_end" => {
+                        writer.write_all(b"volume: 50\nstate: play\nsong: 0\nsongid: 1\nOK\n")?;
+                        title = titles.next().unwrap();
+                    }
+                    _ => {}
+                }
+            }
+            Ok(())
+        });
+        let mut source = MpdSource::with_connector(
+            || Ok(Box::new(client) as Box<dyn Connection>),
+            "{title}".parse()?,
+            None,
+            MpdFormatter::default(),
+            MpdFormatter::default(),
+            StatusIconsSet::themed(IconTheme::Ascii, None, None, None, None, None),
+            "N/A".to_owned(),
+        )?;
+        let mut content = String::new();
+        source
+            .current_format()
+            .format_with_source(&source, &mut content)?;
+        assert_eq!(content, "One");
+        let (mut prefix, mut suffix) = (String::new(), String::new());
+        assert_eq!(
+            source.get(&mut content, &mut prefix, &mut suffix)?,
+            ContentChange::Running
+        );
+        assert_eq!(content, "Two");
+        drop(source);
+        mpd.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn escape_braces_test() {
+        let icons = StatusIconsSet::new(
+            "abc".parse().unwrap(),
+            "de".parse().unwrap(),
+            "fg".parse().unwrap(),
+            "hi".parse().unwrap(),
+            "jk".parse().unwrap(),
+        );
+        let song = Song {
+            title: Some("{Untitled}".to_owned()),
+            ..Default::default()
+        };
+        let format = "{{{title}}} {artist}".parse::<MpdFormatter>().unwrap();
+        let mut f = String::new();
+        let status = Status::default();
+        format
+            .format(&icons, Some(&song), &status, "}N/A{", false, &mut f)
+            .unwrap();
+        assert_eq!(f, "{{Untitled}} }N/A{");
+        f.clear();
+        format
+            .format(&icons, Some(&song), &status, "}N/A{", true, &mut f)
+            .unwrap();
+        assert_eq!(f, "{{{{Untitled}}}} }}N/A{{");
+        assert_eq!(
+            f.parse::<MpdFormatter>()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            [&Placeholder::String("{{Untitled}} }N/A{".to_owned())]
+        );
+        f.clear();
+        format
+            .format(&icons, None, &status, "", true, &mut f)
+            .unwrap();
+        assert_eq!(f, "{{}} ");
+    }
+
+    #[test]
+    fn basename_test() {
+        let file = "Artist/Album (2001)/01. Track.flac";
+        assert_eq!(basename(file, true), Some("01. Track.flac"));
+        assert_eq!(basename(file, false), Some("01. Track"));
+        assert_eq!(basename("song", false), Some("song"));
+        assert_eq!(basename("", true), None);
+        assert_eq!(
+            "{basename}{basename:stem}"
+                .parse::<MpdFormatter>()
+                .unwrap()
+                .0,
+            vec![Placeholder::Basename(true), Placeholder::Basename(false)]
+        );
+        assert_eq!(
+            "{basename}-{basename:stem}"
+                .parse::<MpdFormatter>()
+                .unwrap()
+                .to_string(),
+            "{basename}-{basename:stem}"
+        );
+        assert!("{basename:ext}".parse::<MpdFormatter>().is_err());
+    }
+
+    #[test]
+    fn display_name_test() {
+        assert_eq!(
+            display_name(Some("Title"), "music/song.flac"),
+            Some("Title")
+        );
+        assert_eq!(display_name(None, "music/song.flac"), Some("song.flac"));
+        assert_eq!(display_name(Some(""), "music/song.flac"), Some("song.flac"));
+        assert_eq!(display_name(None, ""), None);
+    }
+
+    #[test]
+    fn pad_test() {
+        let icons = "ab".parse::<StatusIcons>().unwrap();
+        let mut f = String::new();
+        icons.write(true, "1:2".parse().unwrap(), &mut f).unwrap();
+        icons.write(false, "2".parse().unwrap(), &mut f).unwrap();
+        icons.write(true, Pad::default(), &mut f).unwrap();
+        assert_eq!(f, " a  b  a");
+        let only_enabled = "a".parse::<StatusIcons>().unwrap();
+        only_enabled
+            .write(false, "1:1".parse().unwrap(), &mut f)
+            .unwrap();
+        assert_eq!(f, " a  b  a");
+    }
+
+    #[test]
+    fn multi_char_icons() {
+        let icons = StatusIcons::parse("[P],[ ]", Some(",")).unwrap();
+        let mut f = String::new();
+        icons.write(true, Pad::default(), &mut f).unwrap();
+        icons.write(false, "1:1".parse().unwrap(), &mut f).unwrap();
+        assert_eq!(f, "[P] [ ] ");
+        let states = StateStatusIcons::parse("▶️ | ⏸ | ⏹", Some(" | ")).unwrap();
+        assert_eq!(states.get_icon(State::Play), "▶️");
+        assert_eq!(states.get_icon(State::Stop), "⏹");
+        let single_chars = "abc".parse::<StateStatusIcons>().unwrap();
+        assert_eq!(single_chars.get_icon(State::Pause), "b");
+        assert!(StateStatusIcons::parse("a,b", Some(",")).is_err());
+        assert!(StateStatusIcons::parse("a,b,c,d", Some(",")).is_err());
+        assert!(matches!(
+            StatusIcons::parse("a,", Some(",")),
+            Err(IconSetParseError::EmptyIcon)
+        ));
+        assert!(matches!(
+            StatusIcons::parse("", Some(",")),
+            Err(IconSetParseError::EmptyIcon)
+        ));
+    }
+
+    #[test]
+    fn comma_as_icon() {
+        let states = "a,b".parse::<StateStatusIcons>().unwrap();
+        assert_eq!(states.get_icon(State::Play), "a");
+        assert_eq!(states.get_icon(State::Pause), ",");
+        assert_eq!(states.get_icon(State::Stop), "b");
+        let icons = ",-".parse::<StatusIcons>().unwrap();
+        assert_eq!(icons.get_icon(true), Some(","));
+        assert_eq!(icons.get_icon(false), Some("-"));
+        assert!("ab,".parse::<StatusIcons>().is_err());
+    }
 }
@@ -0,0 +1,534 @@
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, IsTerminal, StdoutLock, Write},
+    iter,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+
+/// Erases everything from the cursor to the end of the line.
+const CLEAR_LINE: &[u8] = b"\x1b[K";
+
+/// Size of the little-endian frame length that starts every binary record.
+const RECORD_HEADER: usize = 4;
+
+/// Writes to a socket client that take longer than this fail and disconnect it.
+#[cfg(unix)]
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Frames a socket client may fall behind by before it is disconnected.
+#[cfg(unix)]
+const CLIENT_QUEUE: usize = 16;
+
+/// Queue of frames for the writer thread of a socket client.
+#[cfg(unix)]
+type ClientQueue = SyncSender<Arc<[u8]>>;
+
+/// How leftovers of a previous iteration are erased.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClearStyle {
+    /// `CLEAR_LINE` escape sequence, understood by Unix terminals.
+    Escape,
+    /// Spaces over the rest of the previous iteration, for consoles without escape sequences.
+    Spaces,
+}
+
+impl ClearStyle {
+    fn native() -> Self {
+        if cfg!(windows) {
+            ClearStyle::Spaces
+        } else {
+            ClearStyle::Escape
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Sink {
+    Stdout(StdoutLock<'static>),
+    File {
+        path: PathBuf,
+        file: File,
+    },
+    #[cfg(unix)]
+    Socket(Broadcast),
+}
+
+/// Unix socket that sends every frame to all of its connected clients,
+/// they are accepted on a background thread. Every client has a thread of its own
+/// writing frames from a bounded queue, so a slow client never holds up the others.
+#[cfg(unix)]
+#[derive(Debug)]
+struct Broadcast {
+    path: PathBuf,
+    clients: Arc<Mutex<Vec<ClientQueue>>>,
+}
+
+#[cfg(unix)]
+impl Broadcast {
+    /// Binds the socket, a stale socket left at the path is replaced.
+    fn bind(path: PathBuf) -> io::Result<Self> {
+        if fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = Arc::clone(&clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if stream.set_write_timeout(Some(CLIENT_TIMEOUT)).is_ok() {
+                    accepted.lock().unwrap().push(Self::spawn_writer(stream));
+                }
+            }
+        });
+        Ok(Self { path, clients })
+    }
+
+    /// Writes frames from the returned queue to `stream` until either of them is closed.
+    fn spawn_writer(mut stream: UnixStream) -> ClientQueue {
+        let (sender, receiver) = mpsc::sync_channel::<Arc<[u8]>>(CLIENT_QUEUE);
+        thread::spawn(move || {
+            for frame in receiver {
+                if stream.write_all(&frame).is_err() {
+                    return;
+                }
+            }
+        });
+        sender
+    }
+
+    /// Queues `buffer` for every client without waiting for any of them,
+    /// the ones that are gone or have a full queue are dropped.
+    fn send(&self, buffer: &[u8]) {
+        let frame = Arc::<[u8]>::from(buffer);
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.try_send(Arc::clone(&frame)).is_ok());
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Broadcast {
+    fn drop(&mut self) {
+        self.clients.lock().unwrap().clear();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum When {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Alignment {
+    Left,
+    Right,
+}
+
+/// Destination for iterations, each one is composed in a reusable buffer
+/// and then written out with a single `write_all` and `flush`.
+#[derive(Debug)]
+pub struct Output {
+    sink: Sink,
+    buffer: Vec<u8>,
+    field: Option<(usize, Alignment)>,
+    clear_line: bool,
+    clear_style: ClearStyle,
+    last_len: usize,
+    binary: bool,
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self::new(Sink::Stdout(io::stdout().lock()))
+    }
+
+    /// Opens an output for the path, `-` stands for stdout.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        if path.as_os_str() == "-" {
+            return Ok(Self::stdout());
+        }
+        Ok(Self::new(Sink::File {
+            file: open_file(&path)?,
+            path,
+        }))
+    }
+
+    /// Binds a Unix socket at the path and sends iterations to every connected client.
+    pub fn socket(path: PathBuf) -> io::Result<Self> {
+        #[cfg(unix)]
+        return Ok(Self::new(Sink::Socket(Broadcast::bind(path)?)));
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Unix sockets are not supported here: {}", path.display()),
+        ));
+    }
+
+    fn new(sink: Sink) -> Self {
+        Self {
+            sink,
+            buffer: Vec::new(),
+            field: None,
+            clear_line: false,
+            clear_style: ClearStyle::native(),
+            last_len: 0,
+            binary: false,
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        match &self.sink {
+            Sink::Stdout(lock) => lock.is_terminal(),
+            Sink::File { file, .. } => file.is_terminal(),
+            #[cfg(unix)]
+            Sink::Socket(_) => false,
+        }
+    }
+
+    /// Erases leftovers of a previous longer iteration after each one,
+    /// `When::Auto` enables it only for terminals.
+    pub fn clear_line(mut self, when: When) -> Self {
+        self.clear_line = match when {
+            When::Auto => self.is_terminal(),
+            When::Always => true,
+            When::Never => false,
+        };
+        self
+    }
+
+    /// Pads every iteration with spaces up to `width` characters.
+    pub fn field(mut self, width: usize, alignment: Alignment) -> Self {
+        self.field = Some((width, alignment));
+        self
+    }
+
+    /// Width that iterations are padded to, if any.
+    pub fn field_width(&self) -> Option<usize> {
+        self.field.map(|(width, _)| width)
+    }
+
+    /// Writes every frame as a binary record instead: its length in bytes as a little-endian
+    /// `u32` followed by its UTF-8 bytes, with no terminator and no clearing.
+    pub fn binary(mut self, enabled: bool) -> Self {
+        self.binary = enabled;
+        self
+    }
+
+    /// Writes a frame followed by a terminator, reopening the file if its reader is gone.
+    pub fn write_frame(&mut self, frame: &str, terminator: char) -> io::Result<()> {
+        self.buffer.clear();
+        if self.binary {
+            self.buffer.extend_from_slice(&[0; RECORD_HEADER]);
+        }
+        let frame_len = frame.chars().count();
+        let (left, right) = match self.field {
+            Some((width, alignment)) => {
+                let pad = width.saturating_sub(frame_len);
+                match alignment {
+                    Alignment::Left => (0, pad),
+                    Alignment::Right => (pad, 0),
+                }
+            }
+            None => (0, 0),
+        };
+        self.buffer.extend(iter::repeat_n(b' ', left));
+        self.buffer.extend_from_slice(frame.as_bytes());
+        self.buffer.extend(iter::repeat_n(b' ', right));
+        let len = left + frame_len + right;
+        if self.binary {
+            let record_len = u32::try_from(self.buffer.len() - RECORD_HEADER)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Frame is too long"))?;
+            self.buffer[..RECORD_HEADER].copy_from_slice(&record_len.to_le_bytes());
+            return self.write_buffer();
+        }
+        if self.clear_line {
+            match self.clear_style {
+                ClearStyle::Escape => self.buffer.extend_from_slice(CLEAR_LINE),
+                ClearStyle::Spaces => self
+                    .buffer
+                    .extend(iter::repeat_n(b' ', self.last_len.saturating_sub(len))),
+            }
+        }
+        self.last_len = len;
+        write!(self.buffer, "{}", terminator)?;
+        self.write_buffer()
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        loop {
+            match self.flush_buffer() {
+                Err(e) if is_reader_gone(&e) => self.reopen()?,
+                result => return result,
+            }
+        }
+    }
+
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        let writer: &mut dyn Write = match &mut self.sink {
+            Sink::Stdout(lock) => lock,
+            Sink::File { file, .. } => file,
+            #[cfg(unix)]
+            Sink::Socket(broadcast) => {
+                broadcast.send(&self.buffer);
+                return Ok(());
+            }
+        };
+        writer.write_all(&self.buffer)?;
+        writer.flush()
+    }
+
+    /// Opens the file again. The open is blocking, so for a FIFO it waits until the next reader shows up.
+    fn reopen(&mut self) -> io::Result<()> {
+        if let Sink::File { path, file } = &mut self.sink {
+            *file = open_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn open_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+}
+
+/// A write to a FIFO fails with a broken pipe once its reader is gone,
+/// character devices and sockets whose other end went away may answer with ENXIO instead.
+fn is_reader_gone(e: &io::Error) -> bool {
+    /// "No such device or address", the same number on Linux, the BSDs and macOS.
+    const ENXIO: i32 = 6;
+    e.kind() == io::ErrorKind::BrokenPipe || (cfg!(unix) && e.raw_os_error() == Some(ENXIO))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, io, process};
+
+    use anyhow::Result;
+
+    use super::{Alignment, ClearStyle, Output, When};
+
+    #[test]
+    fn frames_to_file() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-output-{}", process::id()));
+        let mut output = Output::open(path.clone())?;
+        output.write_frame("first", '\n')?;
+        output.write_frame("second", '\n')?;
+        output.write_frame("third", '\r')?;
+        let written = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(written, "first\nsecond\nthird\r");
+        Ok(())
+    }
+
+    #[test]
+    fn field_padding() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-field-{}", process::id()));
+        let mut output = Output::open(path.clone())?.field(10, Alignment::Right);
+        output.write_frame("abcde", '\n')?;
+        output.write_frame("abcdefghijkl", '\n')?;
+        assert_eq!(fs::read_to_string(&path)?, "     abcde\nabcdefghijkl\n");
+        let mut output = Output::open(path.clone())?.field(10, Alignment::Left);
+        output.write_frame("abcde", '\n')?;
+        assert_eq!(fs::read_to_string(&path)?, "abcde     \n");
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn clear_line() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-clear-{}", process::id()));
+        let mut output = Output::open(path.clone())?.clear_line(When::Auto);
+        output.write_frame("long frame", '\r')?;
+        assert_eq!(fs::read_to_string(&path)?, "long frame\r");
+        let mut output = Output::open(path.clone())?.clear_line(When::Always);
+        output.write_frame("long frame", '\r')?;
+        output.write_frame("short", '\r')?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "long frame\x1b[K\rshort\x1b[K\r"
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn binary_records() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-binary-{}", process::id()));
+        let mut output = Output::open(path.clone())?
+            .binary(true)
+            .field(4, Alignment::Right);
+        for frame in ["ab", "line\nbreak", "", "ёж"] {
+            output.write_frame(frame, '\n')?;
+        }
+        let mut records = fs::read(&path)?;
+        fs::remove_file(&path)?;
+        let mut frames = vec![];
+        while !records.is_empty() {
+            let (header, rest) = records.split_at(4);
+            let len = u32::from_le_bytes(header.try_into()?) as usize;
+            frames.push(String::from_utf8(rest[..len].to_vec())?);
+            records = rest[len..].to_vec();
+        }
+        assert_eq!(frames, ["  ab", "line\nbreak", "    ", "  ёж"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo_reader_reopens() -> Result<()> {
+        use std::{fs::File, io::Read, process::Command, sync::mpsc, thread, time::Duration};
+
+        use super::is_reader_gone;
+
+        assert!(is_reader_gone(&io::Error::from_raw_os_error(6)));
+        assert!(is_reader_gone(&io::ErrorKind::BrokenPipe.into()));
+        let path = env::temp_dir().join(format!("mergneh-fifo-{}", process::id()));
+        assert!(Command::new("mkfifo").arg(&path).status()?.success());
+        let (closed, reader_closed) = mpsc::channel();
+        let reader = thread::spawn({
+            let path = path.clone();
+            move || -> io::Result<[String; 2]> {
+                let mut first = [0; 4];
+                File::open(&path)?.read_exact(&mut first)?;
+                closed.send(()).unwrap();
+                // opening again right away would find the old writer still open
+                thread::sleep(Duration::from_millis(100));
+                let mut second = String::new();
+                File::open(&path)?.read_to_string(&mut second)?;
+                Ok([String::from_utf8_lossy(&first).into_owned(), second])
+            }
+        });
+        let mut output = Output::open(path.clone())?;
+        output.write_frame("one", '\n')?;
+        reader_closed.recv()?;
+        // the write fails with nobody reading, the output waits for the next reader and retries
+        output.write_frame("two", '\n')?;
+        drop(output);
+        let frames = reader.join().unwrap()?;
+        fs::remove_file(&path)?;
+        assert_eq!(frames, ["one\n", "two\n"]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn socket() -> Result<()> {
+        use std::{
+            io::{BufRead, BufReader},
+            os::unix::net::UnixStream,
+            thread,
+            time::Duration,
+        };
+
+        use super::Sink;
+
+        let path = env::temp_dir().join(format!("mergneh-socket-{}", process::id()));
+        let mut output = Output::socket(path.clone())?;
+        let Sink::Socket(broadcast) = &output.sink else {
+            unreachable!()
+        };
+        let clients = broadcast.clients.clone();
+        let first = BufReader::new(UnixStream::connect(&path)?);
+        let second = UnixStream::connect(&path)?;
+        while clients.lock().unwrap().len() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        output.write_frame("one", '\n')?;
+        drop(second);
+        // the writer of `second` fails on its next frame, the client is dropped on the one after
+        while clients.lock().unwrap().len() > 1 {
+            output.write_frame("two", '\n')?;
+            thread::sleep(Duration::from_millis(1));
+        }
+        output.write_frame("three", '\n')?;
+        drop(output);
+        assert!(!path.exists());
+        let frames = first.lines().collect::<io::Result<Vec<_>>>()?;
+        assert_eq!(frames.first().map(String::as_str), Some("one"));
+        assert_eq!(frames.last().map(String::as_str), Some("three"));
+        assert!(frames[1..frames.len() - 1].iter().all(|f| f == "two"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn slow_socket_client() -> Result<()> {
+        use std::{
+            io::Read,
+            os::unix::net::UnixStream,
+            thread,
+            time::{Duration, Instant},
+        };
+
+        use super::{Sink, CLIENT_QUEUE};
+
+        let path = env::temp_dir().join(format!("mergneh-slow-socket-{}", process::id()));
+        let mut output = Output::socket(path.clone())?;
+        let Sink::Socket(broadcast) = &output.sink else {
+            unreachable!()
+        };
+        let clients = broadcast.clients.clone();
+        let mut reading = UnixStream::connect(&path)?;
+        let _stalled = UnixStream::connect(&path)?;
+        while clients.lock().unwrap().len() < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let reader = thread::spawn(move || -> io::Result<usize> {
+            let mut read = Vec::new();
+            reading.read_to_end(&mut read)?;
+            Ok(read.len())
+        });
+        let frame = "x".repeat(64 * 1024);
+        let frames = 4 * CLIENT_QUEUE;
+        let start = Instant::now();
+        for _ in 0..frames {
+            output.write_frame(&frame, '\n')?;
+            thread::sleep(Duration::from_millis(1));
+        }
+        // nobody waited for the stalled client to make room
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(clients.lock().unwrap().len(), 1);
+        drop(output);
+        assert_eq!(reader.join().unwrap()?, frames * (frame.len() + 1));
+        Ok(())
+    }
+
+    #[test]
+    fn clear_line_with_spaces() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-clear-spaces-{}", process::id()));
+        let mut output = Output::open(path.clone())?.clear_line(When::Always);
+        output.clear_style = ClearStyle::Spaces;
+        output.write_frame("long frame", '\r')?;
+        output.write_frame("short", '\r')?;
+        output.write_frame("longer", '\r')?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "long frame\rshort     \rlonger\r"
+        );
+        fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -1,13 +1,35 @@
-use std::{fmt::Write, io, time::Duration};
+use std::{
+    fmt::Write,
+    io, iter, mem,
+    sync::mpsc::Receiver,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
+use clap::ValueEnum;
 use ticker::Ticker;
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
+    control::Control,
+    output::Output,
+    stats::Stats,
     text_source::{Content, ContentChange},
     utils::replace_newline,
     TextSource,
 };
 
+type Callback = dyn FnMut(&str) -> anyhow::Result<()>;
+
+/// Called with the new content before anything is recomputed from it.
+pub struct OnChange(Box<Callback>);
+
+impl std::fmt::Debug for OnChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OnChange")
+    }
+}
+
 #[derive(Debug)]
 pub struct RunningText {
     source: TextSource,
@@ -18,16 +40,56 @@ pub struct RunningText {
     suffix: String,
     replacements: Vec<(String, String)>,
     window_size: usize,
-    repeat: bool,
+    scroll_mode: ScrollMode,
     reset_on_change: bool,
     text: String,
     full_content_char_len: usize,
     content_char_len: usize,
     i: usize,
     byte_offset: usize,
+    changes: ContentChange,
+    word_scroll: bool,
+    max_len: Option<usize>,
+    ellipsis: Option<String>,
+    skip_identical: bool,
+    last_frame: String,
+    cycles: Option<usize>,
+    completed_cycles: usize,
+    wrapped: bool,
+    /// Characters `OnceThenClear` may still advance by, `None` once the text was cleared.
+    chars_left: Option<usize>,
+    speed: f64,
+    progress: f64,
+    separator_mode: SeparatorMode,
+    on_change: Option<OnChange>,
+    stats: Option<Stats>,
+    reload: bool,
+}
+
+/// How the separator takes part in scrolling of repeating content: with `InScroll` it scrolls
+/// like the content and the window can start inside it, with `GapOnly` it only fills the gap
+/// and the window jumps back to the start right after the last character of the content.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SeparatorMode {
+    InScroll,
+    GapOnly,
+}
+
+/// When the content scrolls: `Always` even if it fits, `Overflow` only if it does not
+/// and `Once` a single time, holding the end. `OnceThenClear` is like `Once`, but iterations
+/// become empty after that. `Truncate` never scrolls and cuts the content to the window.
+/// Only the first two wrap around, so only they get the separator appended to the content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScrollMode {
+    Always,
+    Overflow,
+    Once,
+    OnceThenClear,
+    Truncate,
 }
 
 impl RunningText {
+    /// A `window_size` of 0 is allowed, iterations are then just the prefix and the suffix.
     pub fn new(
         mut source: TextSource,
         window_size: usize,
@@ -44,13 +106,10 @@ impl RunningText {
         } = source.get_initial_content()?;
         replace_newline(&mut content, &newline);
         replace_newline(&mut separator, &newline);
-        let content_len = content.len();
-        let count = content[..content_len].chars().count();
-        content += &separator;
         let mut new = RunningText {
             source,
             text: String::new(),
-            full_content_char_len: count + content[content_len..].chars().count(),
+            full_content_char_len: 0,
             content,
             newline,
             separator,
@@ -58,51 +117,385 @@ impl RunningText {
             prefix,
             suffix,
             window_size,
-            repeat,
+            scroll_mode: if repeat {
+                ScrollMode::Always
+            } else {
+                ScrollMode::Overflow
+            },
             reset_on_change,
-            content_char_len: count,
+            content_char_len: 0,
             i: 0,
             byte_offset: 0,
+            changes: ContentChange::empty(),
+            word_scroll: false,
+            max_len: None,
+            ellipsis: None,
+            skip_identical: false,
+            last_frame: String::new(),
+            cycles: None,
+            completed_cycles: 0,
+            wrapped: false,
+            chars_left: None,
+            speed: 1.0,
+            progress: 1.0,
+            separator_mode: SeparatorMode::InScroll,
+            on_change: None,
+            stats: None,
+            reload: false,
         };
-        if new.does_content_fit() {
-            write!(
-                new.text,
-                "{}{}{}",
-                new.prefix,
-                &new.content[..content_len],
-                new.suffix
-            )?;
-            new.apply_replacements();
-        }
+        new.update_content()?;
         Ok(new)
     }
     pub fn get_raw_content(&self) -> &str {
         &self.content
     }
-    pub fn run_on_terminal(self, duration: Duration, newline: bool) -> anyhow::Result<()> {
-        let tick = Ticker::new(self, duration);
-        for text in tick {
-            print!("{}{}", text?, if newline { '\n' } else { '\r' });
-            io::Write::flush(&mut io::stdout())?;
+    pub fn explain<W: Write>(&self, f: &mut W) -> std::fmt::Result {
+        self.source.explain(f)?;
+        writeln!(f, "window: {}", self.window_size)?;
+        writeln!(f, "scroll mode: {:?}", self.scroll_mode)?;
+        writeln!(f, "reset on change: {}", self.reset_on_change)?;
+        writeln!(f, "word scroll: {}", self.word_scroll)?;
+        writeln!(f, "speed: {:?}", self.speed)?;
+        writeln!(f, "cycles: {:?}", self.cycles)?;
+        writeln!(f, "max length: {:?}", self.max_len)?;
+        writeln!(f, "ellipsis: {:?}", self.ellipsis)?;
+        writeln!(f, "separator: {:?}", self.separator)?;
+        writeln!(f, "separator mode: {:?}", self.separator_mode)?;
+        writeln!(f, "newline: {:?}", self.newline)?;
+        writeln!(f, "prefix: {:?}", self.prefix)?;
+        writeln!(f, "suffix: {:?}", self.suffix)?;
+        writeln!(f, "replacements: {:?}", self.replacements)
+    }
+    /// Writes an iteration every tick, source errors and changes are logged into `log` if present.
+    /// Commands from `control` are applied before each tick, no iterations are written while paused.
+    /// With `align_ticks` every tick lands on a multiple of `duration` since the Unix epoch.
+    pub fn run<W: io::Write>(
+        self,
+        duration: Duration,
+        newline: bool,
+        output: &mut Output,
+        log: Option<W>,
+        control: Option<Receiver<Control>>,
+        align_ticks: bool,
+    ) -> anyhow::Result<()> {
+        let tick: Box<dyn Iterator<Item = ()>> = if align_ticks {
+            Box::new(boundary_ticks(
+                duration,
+                || {
+                    SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap_or_default()
+                },
+                thread::sleep,
+            ))
+        } else {
+            Box::new(Ticker::new(iter::repeat(()), duration).into_iter())
+        };
+        let terminator = if newline { '\n' } else { '\r' };
+        self.run_ticks(tick, terminator, output, log, control)
+    }
+    /// The loop of `run`, writing an iteration each time `tick` yields.
+    fn run_ticks<W: io::Write>(
+        mut self,
+        tick: impl Iterator<Item = ()>,
+        terminator: char,
+        output: &mut Output,
+        mut log: Option<W>,
+        control: Option<Receiver<Control>>,
+    ) -> anyhow::Result<()> {
+        if log.is_some() {
+            self.source.collect_diagnostics();
+        }
+        let mut paused = false;
+        for _ in tick {
+            for command in control.iter().flat_map(Receiver::try_iter) {
+                match command {
+                    Control::TogglePause => paused = !paused,
+                    Control::Restart => self.reload(),
+                    Control::Quit => return Ok(()),
+                    Control::Window(window_size) => {
+                        let rejection = match output.field_width() {
+                            _ if window_size == 0 => Some("it must be at least 1".to_owned()),
+                            Some(width) if width < window_size => {
+                                Some(format!("the field width ({width}) is smaller"))
+                            }
+                            _ => None,
+                        };
+                        match (rejection, log.as_mut()) {
+                            (None, _) => self.set_window(window_size)?,
+                            (Some(reason), Some(log)) => {
+                                writeln!(log, "Ignoring window size {window_size}: {reason}")?
+                            }
+                            (Some(_), None) => {}
+                        }
+                    }
+                }
+            }
+            if paused {
+                continue;
+            }
+            let start = Instant::now();
+            if !self.tick(output, terminator, log.as_mut())? {
+                break;
+            }
+            let changed = !self.changes.is_empty();
+            if let Some(summary) = self
+                .stats
+                .as_mut()
+                .and_then(|stats| stats.record(start, Instant::now(), changed))
+            {
+                eprintln!("{}", summary);
+            }
         }
         Ok(())
     }
-    pub fn print_once(&mut self, mut i: usize, prev_content: &str) -> anyhow::Result<usize> {
-        if prev_content != self.content {
+    /// Returns `false` when there is nothing left to show.
+    fn tick<W: io::Write>(
+        &mut self,
+        output: &mut Output,
+        terminator: char,
+        log: Option<&mut W>,
+    ) -> anyhow::Result<bool> {
+        match (self.advance(), log) {
+            (Some(Ok(text)), log) => {
+                output.write_frame(text, terminator)?;
+                if let Some(log) = log {
+                    self.log_diagnostics(log)?;
+                    if !self.changes.is_empty() {
+                        let mut changes = String::new();
+                        bitflags::parser::to_writer(&self.changes, &mut changes)?;
+                        writeln!(log, "Source changed: {}", changes)?;
+                    }
+                }
+            }
+            (Some(Err(e)), Some(log)) => {
+                self.log_diagnostics(log)?;
+                writeln!(log, "Source error: {:#}", e)?
+            }
+            (Some(Err(e)), None) => return Err(e),
+            (None, _) => return Ok(false),
+        }
+        Ok(true)
+    }
+    /// Logs how the last command execution went if it failed or wrote to stderr.
+    fn log_diagnostics<W: io::Write>(&self, log: &mut W) -> io::Result<()> {
+        match self.source.diagnostics() {
+            Some((cmd, diagnostics)) if diagnostics.is_notable() => {
+                writeln!(log, "Command {} finished with {}", cmd, diagnostics)
+            }
+            _ => Ok(()),
+        }
+    }
+    /// Prints the iteration at `i`, starting over if `prev_content` differs from the current one.
+    pub fn print_once(
+        &mut self,
+        mut i: usize,
+        prev_content: Option<&str>,
+    ) -> anyhow::Result<usize> {
+        if prev_content.is_some_and(|c| c != self.content) {
             i = 0;
         }
-        self.i = i;
-        self.byte_offset = self
-            .content
-            .char_indices()
-            .nth(i % self.full_content_char_len)
-            .unwrap()
-            .0;
+        self.seek(i);
         println!("{}", self.next().unwrap()?);
         Ok(self.i)
     }
+    /// Iterations of one full cycle from the current position, just one if the content fits.
+    pub fn full_cycle(&mut self) -> anyhow::Result<Vec<String>> {
+        let start = self.i;
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next() {
+            frames.push(frame?);
+            if self.does_content_fit()
+                || self.i == start
+                || frames.len() >= self.full_content_char_len
+            {
+                break;
+            }
+        }
+        Ok(frames)
+    }
+    /// Moves the window so that the next iteration starts from the `i`-th character.
+    pub fn seek(&mut self, i: usize) {
+        if self.full_content_char_len == 0 {
+            self.i = 0;
+            self.byte_offset = 0;
+            return;
+        }
+        self.i = i % self.cycle_len();
+        self.byte_offset = self.content.char_indices().nth(self.i).unwrap().0;
+    }
+    /// Whether the window went past the end of the content and started over
+    /// while producing the last iteration, completing a cycle.
+    pub fn at_wrap_boundary(&self) -> bool {
+        self.wrapped
+    }
+    /// Makes the next iteration poll the source and start over from the beginning
+    /// of its content, as if it was new.
+    pub fn reload(&mut self) {
+        self.reload = true;
+    }
+    /// Changes the window size, scrolling goes on from the same position
+    /// except with `once_then_clear`, which starts over with the new window.
+    pub fn set_window(&mut self, window_size: usize) -> std::fmt::Result {
+        self.window_size = window_size;
+        if self.scroll_mode == ScrollMode::OnceThenClear {
+            self.seek(0);
+        }
+        self.reset_budget();
+        if self.does_content_fit() {
+            self.seek(0);
+            self.fill_static_text()?;
+        }
+        Ok(())
+    }
+    /// Moves the window so that the next iteration shows the end of the content.
+    pub fn seek_end(&mut self) {
+        self.seek(self.content_char_len.saturating_sub(self.window_size));
+    }
+    /// Moves the window `offset` characters into the content, at most to its last character.
+    pub fn seek_clamped(&mut self, offset: usize) {
+        self.seek(offset.min(self.content_char_len.saturating_sub(1)));
+    }
+    /// Makes the window jump to the start of the next word instead of the next character.
+    pub fn word_scroll(mut self, enabled: bool) -> Self {
+        self.word_scroll = enabled;
+        self
+    }
+    /// Skips iterations that look the same as the previous one, e.g. in long runs of spaces.
+    pub fn skip_identical(mut self, enabled: bool) -> Self {
+        self.skip_identical = enabled;
+        self
+    }
+    /// Stops scrolling after `cycles` full cycles and holds the first iteration,
+    /// counting starts over when the content changes.
+    pub fn cycles(mut self, cycles: Option<usize>) -> Self {
+        self.cycles = cycles;
+        self
+    }
+    /// Shows `initial` instead of empty content until the source produces something.
+    pub fn initial(mut self, initial: Option<String>) -> anyhow::Result<Self> {
+        if let Some(initial) = initial.filter(|_| self.content_char_len == 0) {
+            self.content = initial;
+            replace_newline(&mut self.content, &self.newline);
+            self.update_content()?;
+        }
+        Ok(self)
+    }
+    /// Cuts the content down to `max_len` graphemes, ending with `ellipsis` if it was cut.
+    pub fn max_len(mut self, max_len: usize, ellipsis: Option<String>) -> anyhow::Result<Self> {
+        self.max_len = Some(max_len);
+        self.ellipsis = ellipsis;
+        self.strip_separator();
+        self.update_content()?;
+        Ok(self)
+    }
+    /// Scrolls content that does not fit once and holds its end instead of wrapping around,
+    /// content that fits stays static as if repeating was disabled.
+    pub fn scroll_once(mut self, enabled: bool) -> anyhow::Result<Self> {
+        if enabled {
+            self.strip_separator();
+            self.scroll_mode = ScrollMode::Once;
+            self.update_content()?;
+        }
+        Ok(self)
+    }
+    /// Like `scroll_once`, but iterations are empty once the end of the content was shown,
+    /// content that fits is shown as long as scrolling by the width of the window would take.
+    /// It all starts over when the content changes.
+    pub fn once_then_clear(mut self, enabled: bool) -> anyhow::Result<Self> {
+        if enabled {
+            self.strip_separator();
+            self.scroll_mode = ScrollMode::OnceThenClear;
+            self.update_content()?;
+        }
+        Ok(self)
+    }
+    /// Cuts content that does not fit to the window instead of scrolling it.
+    pub fn truncate_to_window(mut self, enabled: bool) -> anyhow::Result<Self> {
+        if enabled {
+            self.strip_separator();
+            self.scroll_mode = ScrollMode::Truncate;
+            self.update_content()?;
+        }
+        Ok(self)
+    }
+    /// Characters to move the window by per iteration, fractions add up over iterations,
+    /// e.g. with 0.5 every iteration is shown twice. Must be positive.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+    pub fn separator_mode(mut self, mode: SeparatorMode) -> Self {
+        self.separator_mode = mode;
+        self
+    }
+    /// Prints a summary of tick timings to stderr every `window` while running.
+    pub fn stats(mut self, window: Option<Duration>) -> Self {
+        self.stats = window.map(Stats::new);
+        self
+    }
+    /// Calls `on_change` each time the source produces new content, a failing call is a source error.
+    pub fn on_change<F: FnMut(&str) -> anyhow::Result<()> + 'static>(
+        mut self,
+        on_change: F,
+    ) -> Self {
+        self.on_change = Some(OnChange(Box::new(on_change)));
+        self
+    }
+    /// The separator appended to the content, empty if the content never wraps around.
+    fn separator(&self) -> &str {
+        match self.scroll_mode {
+            ScrollMode::Always | ScrollMode::Overflow => &self.separator,
+            ScrollMode::Once | ScrollMode::OnceThenClear | ScrollMode::Truncate => "",
+        }
+    }
+    fn wraps_around(&self) -> bool {
+        matches!(self.scroll_mode, ScrollMode::Always | ScrollMode::Overflow)
+    }
+    fn strip_separator(&mut self) {
+        let len = self.content.len() - self.separator().len();
+        self.content.truncate(len);
+    }
+    /// Number of steps before the window is back at the start.
+    fn cycle_len(&self) -> usize {
+        match self.separator_mode {
+            SeparatorMode::GapOnly if self.content_char_len > 0 => self.content_char_len,
+            _ => self.full_content_char_len,
+        }
+    }
+    /// Keeps the window from going past the end of the content.
+    fn hold_at_end(&mut self) {
+        let last = self.content_char_len.saturating_sub(self.window_size);
+        if self.i > last {
+            self.seek(last);
+        }
+    }
+    fn step(&mut self) {
+        self.i += 1;
+        if self.i >= self.cycle_len() {
+            self.i = 0;
+            self.byte_offset = 0;
+            self.wrapped = true;
+            return;
+        }
+        self.byte_offset += &self.content[self.byte_offset..]
+            .chars()
+            .next()
+            .map(char::len_utf8)
+            .unwrap_or_default();
+    }
+    fn is_word_start(&self) -> bool {
+        let current = self.content[self.byte_offset..].chars().next();
+        let previous = self.content[..self.byte_offset].chars().next_back();
+        current.is_some_and(|c| !c.is_whitespace()) && previous.is_some_and(char::is_whitespace)
+    }
+    /// Truncated content always fits, it is cut to the window when shown.
     fn does_content_fit(&self) -> bool {
-        !self.repeat && self.window_size >= self.content_char_len
+        match self.scroll_mode {
+            ScrollMode::Always => false,
+            ScrollMode::Truncate => true,
+            _ => self.window_size >= self.content_char_len,
+        }
     }
     fn apply_replacements(&mut self) {
         for (src, dest) in self.replacements.iter() {
@@ -121,7 +514,11 @@ impl RunningText {
         }
     }
     fn get_new_content(&mut self) -> anyhow::Result<ContentChange> {
+        if !self.source.is_dynamic() {
+            return Ok(ContentChange::empty());
+        }
         let changes = self.source.get_content(
+            self.i,
             &mut self.content,
             #[cfg(feature = "mpd")]
             &mut self.prefix,
@@ -131,48 +528,159 @@ impl RunningText {
         if !changes.contains(ContentChange::Running) {
             return Ok(changes);
         }
+        if let Some(OnChange(on_change)) = &mut self.on_change {
+            on_change(&self.content)?;
+        }
         replace_newline(&mut self.content, &self.newline);
+        self.update_content()?;
+        Ok(changes)
+    }
+    /// Recomputes everything derived from the content, which must not have the separator yet.
+    /// Content that fits is shown as is, longer content scrolls from the same position
+    /// or from the start if the previous content fit.
+    fn update_content(&mut self) -> std::fmt::Result {
+        if let Some(max_len) = self.max_len {
+            truncate(&mut self.content, max_len, self.ellipsis.as_deref());
+        }
+        let did_fit = self.does_content_fit();
+        self.completed_cycles = 0;
         self.content_char_len = self.content.chars().count();
-        self.content += &self.separator;
-        self.full_content_char_len = self.content_char_len + self.separator.chars().count();
-        if self.reset_on_change {
+        self.full_content_char_len = self.content_char_len + self.separator().chars().count();
+        if self.wraps_around() {
+            self.content += &self.separator;
+        }
+        if self.reset_on_change || did_fit || self.scroll_mode == ScrollMode::OnceThenClear {
             self.i = 0;
             self.byte_offset = 0;
         } else {
-            self.i %= self.full_content_char_len;
-            self.byte_offset = self.content.char_indices().nth(self.i).unwrap().0;
+            self.seek(self.i);
         }
-        Ok(changes)
+        self.reset_budget();
+        if self.does_content_fit() {
+            self.fill_static_text()?;
+        }
+        Ok(())
     }
-}
-
-impl Iterator for RunningText {
-    type Item = anyhow::Result<String>;
-
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Gives `once_then_clear` the characters it takes to show the whole content again.
+    fn reset_budget(&mut self) {
+        self.chars_left = Some(if self.does_content_fit() {
+            self.window_size.max(1)
+        } else {
+            self.content_char_len.saturating_sub(self.window_size) + 1
+        });
+    }
+    /// Recomputes everything derived from the content and moves the window to its start.
+    fn rebuild(&mut self) -> std::fmt::Result {
+        self.strip_separator();
+        self.update_content()?;
+        self.seek(0);
+        self.progress = 1.0;
+        Ok(())
+    }
+    fn fill_static_text(&mut self) -> std::fmt::Result {
+        self.text.clear();
+        let content = &self.content[..self.content.len() - self.separator().len()];
+        let end = content
+            .char_indices()
+            .nth(self.window_size)
+            .map_or(content.len(), |(i, _)| i);
+        write!(
+            self.text,
+            "{}{}{}",
+            &self.prefix,
+            &content[..end],
+            &self.suffix
+        )?;
+        self.apply_replacements();
+        Ok(())
+    }
+    /// Moves the window one step further without allocating a new string.
+    fn advance(&mut self) -> Option<anyhow::Result<&str>> {
         let changes = match self.get_new_content() {
             Ok(c) => c,
             Err(e) => return Some(Err(e)),
         };
+        self.changes = changes;
+        self.wrapped = false;
+        if mem::take(&mut self.reload) {
+            if let Err(e) = self.rebuild() {
+                return Some(Err(e.into()));
+            }
+        }
+        if self.content_char_len == 0 && self.source.is_dynamic() {
+            // the source may produce something later, so just the prefix and the suffix for now
+            if let Err(e) = self.fill_static_text() {
+                return Some(Err(e.into()));
+            };
+            return Some(Ok(&self.text));
+        }
         if self.content.is_empty() {
             return None;
         }
+        let steps = self.steps_at_speed();
+        if self.scroll_mode == ScrollMode::OnceThenClear {
+            // the budget is in characters, so the speed changes how many iterations it lasts
+            self.chars_left = self.chars_left.and_then(|left| left.checked_sub(steps));
+            if self.chars_left.is_none() {
+                self.text.clear();
+                return Some(Ok(&self.text));
+            }
+        }
         if self.does_content_fit() {
             if !changes.is_empty() {
-                self.text.clear();
-                if let Err(e) = write!(
-                    self.text,
-                    "{}{}{}",
-                    &self.prefix,
-                    &self.content[..self.content.len() - self.separator.len()],
-                    &self.suffix
-                ) {
+                if let Err(e) = self.fill_static_text() {
                     return Some(Err(e.into()));
                 };
-                self.apply_replacements();
             }
-            return Some(Ok(self.text.to_owned()));
+            return Some(Ok(&self.text));
+        }
+        if steps == 0 && changes.is_empty() {
+            // not a whole character yet, the previous iteration stays
+            return Some(Ok(&self.text));
+        }
+        if !self.wraps_around() {
+            self.hold_at_end();
+            self.skip(steps.saturating_sub(1));
+            self.hold_at_end();
+            self.render_window();
+            self.hold_at_end();
+            return Some(Ok(&self.text));
+        }
+        if self.cycles.is_some_and(|n| self.completed_cycles >= n) {
+            self.seek(0);
+            self.render_window();
+            self.seek(0);
+            return Some(Ok(&self.text));
+        }
+        self.skip(steps.saturating_sub(1));
+        self.render_window();
+        if self.skip_identical {
+            let mut steps = 1;
+            while self.text == self.last_frame && steps < self.full_content_char_len {
+                self.render_window();
+                steps += 1;
+            }
+            self.last_frame.clone_from(&self.text);
+        }
+        if self.at_wrap_boundary() {
+            self.completed_cycles += 1;
         }
+        Some(Ok(&self.text))
+    }
+    /// Number of whole characters the window moves by in this iteration.
+    fn steps_at_speed(&mut self) -> usize {
+        let steps = self.progress.floor();
+        self.progress += self.speed - steps;
+        steps as usize
+    }
+    /// Moves the window by `steps` characters without rendering it.
+    fn skip(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+    /// Writes the current window into `text` and moves it one step further.
+    fn render_window(&mut self) {
         self.text.clear();
         self.text.push_str(&self.prefix);
         self.text.extend(
@@ -189,27 +697,90 @@ impl Iterator for RunningText {
             remainder -= self.full_content_char_len;
         }
         self.text.extend(self.content.chars().take(remainder));
-        self.i += 1;
-        self.i %= self.full_content_char_len;
-        self.byte_offset += &self.content[self.byte_offset..]
-            .chars()
-            .next()
-            .map(char::len_utf8)
-            .unwrap_or_default();
-        self.byte_offset %= self.content.len();
+        self.step();
+        if self.word_scroll {
+            while self.i != 0 && !self.is_word_start() {
+                self.step();
+            }
+        }
         self.text.push_str(&self.suffix);
         self.apply_replacements();
-        Some(Ok(self.text.clone()))
+    }
+}
+
+/// Time left from `now` until the next multiple of `period`.
+fn until_boundary(now: Duration, period: Duration) -> Duration {
+    if period.is_zero() {
+        return Duration::ZERO;
+    }
+    match now.as_nanos() % period.as_nanos() {
+        0 => Duration::ZERO,
+        elapsed => Duration::from_nanos((period.as_nanos() - elapsed) as u64),
+    }
+}
+
+/// Ticks on multiples of `period` as told by `now`, waiting for them with `sleep`.
+/// A boundary is ticked at most once, ticks that would land on the previous one wait for the next.
+fn boundary_ticks(
+    period: Duration,
+    mut now: impl FnMut() -> Duration,
+    mut sleep: impl FnMut(Duration),
+) -> impl Iterator<Item = ()> {
+    let mut last = None;
+    iter::repeat_with(move || {
+        let now = now();
+        let mut wait = until_boundary(now, period);
+        if last == Some(now + wait) {
+            wait += period;
+        }
+        last = Some(now + wait);
+        sleep(wait);
+    })
+}
+
+/// Truncates `s` to at most `max_len` graphemes, so a cut never splits a grapheme cluster.
+/// The `ellipsis` counts towards `max_len` and is cut too if it does not fit.
+fn truncate(s: &mut String, max_len: usize, ellipsis: Option<&str>) {
+    if s.graphemes(true).count() <= max_len {
+        return;
+    }
+    let ellipsis = ellipsis.unwrap_or_default();
+    let kept = max_len.saturating_sub(ellipsis.graphemes(true).count());
+    let end = s
+        .grapheme_indices(true)
+        .nth(kept)
+        .map_or(s.len(), |(i, _)| i);
+    s.truncate(end);
+    s.extend(ellipsis.graphemes(true).take(max_len - kept));
+}
+
+impl Iterator for RunningText {
+    type Item = anyhow::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance().map(|text| text.map(str::to_owned))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        env, fs, iter, process,
+        rc::Rc,
+        sync::mpsc,
+        time::Duration,
+    };
+
     use anyhow::{Ok, Result};
 
-    use crate::text_source::TextSource;
+    use crate::{
+        control::Control,
+        output::{Alignment, Output},
+        text_source::{CmdSource, ContentChange, TextSource},
+    };
 
-    use super::RunningText;
+    use super::{boundary_ticks, truncate, until_boundary, OnChange, RunningText, SeparatorMode};
 
     macro_rules! assert_text {
         ($var:ident, $($iter:literal),+) => {
@@ -343,6 +914,26 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn crlf() -> Result<()> {
+        for (newline, expected) in [(" ", "a b c"), ("", "abc"), ("\n", "a\nb\nc")] {
+            let script = ["a\r\nb\r\nc", "a\r\nb\r\nc\r"].map(str::to_owned);
+            let mut text = RunningText::new(
+                TextSource::Scripted(script.into()),
+                8,
+                "\r\n".to_owned(),
+                newline.to_owned(),
+                vec![],
+                false,
+                false,
+            )?;
+            assert_eq!(text.get_raw_content(), format!("{expected}{newline}"));
+            assert_eq!(text.next().unwrap()?, expected);
+            assert_eq!(text.next().unwrap()?, expected);
+        }
+        Ok(())
+    }
+
     #[test]
     fn special_chars() -> Result<()> {
         let mut text = RunningText::new(
@@ -443,4 +1034,861 @@ mod tests {
         assert_text!(text, "a &amp; b", "a &amp; b", "a &amp; b", "a &amp; b");
         Ok(())
     }
+
+    #[test]
+    fn stdin_control() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-control-{}", process::id()));
+        let text = |script: &[&str]| {
+            RunningText::new(
+                TextSource::Scripted(script.iter().map(|s| s.to_string()).collect()),
+                4,
+                "".to_owned(),
+                "".to_owned(),
+                vec![],
+                true,
+                false,
+            )
+        };
+        let mut output = Output::open(path.clone())?;
+        let (sender, receiver) = mpsc::channel();
+        sender.send(Control::Quit)?;
+        text(&["Lorem ipsum"])?.run(
+            Duration::from_millis(10),
+            true,
+            &mut output,
+            None::<Vec<u8>>,
+            Some(receiver),
+            false,
+        )?;
+        assert_eq!(fs::read_to_string(&path)?, "");
+        // each command arrives right before its tick, so no timing is involved
+        let (sender, receiver) = mpsc::channel();
+        let commands = [
+            (2, Control::Restart),
+            (4, Control::TogglePause),
+            (6, Control::TogglePause),
+            (8, Control::Quit),
+        ];
+        let tick = (0..).map(move |i| {
+            for (_, command) in commands.iter().filter(|(at, _)| *at == i) {
+                sender.send(*command).unwrap();
+            }
+        });
+        text(&[
+            "Lorem ipsum",
+            "Lorem ipsum",
+            "Lorem ipsum",
+            "Dolor sit amet",
+        ])?
+        .run_ticks(tick, '\n', &mut output, None::<Vec<u8>>, Some(receiver))?;
+        let written = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(
+            written.lines().collect::<Vec<_>>(),
+            ["Lore", "orem", "Dolo", "olor", "lor ", "or s"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn window_control() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-window-control-{}", process::id()));
+        let mut output = Output::open(path.clone())?.field(4, Alignment::Left);
+        let (sender, receiver) = mpsc::channel();
+        let commands = [
+            (1, Control::Window(0)),
+            (4, Control::Window(4)),
+            (5, Control::Window(5)),
+            (7, Control::Quit),
+        ];
+        let tick = (0..).map(move |i| {
+            for (_, command) in commands.iter().filter(|(at, _)| *at == i) {
+                sender.send(*command).unwrap();
+            }
+        });
+        let mut log = vec![];
+        RunningText::new(
+            TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+            3,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?
+        .once_then_clear(true)?
+        .run_ticks(tick, '\n', &mut output, Some(&mut log), Some(receiver))?;
+        let written = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        // the new window brings back the budget that ran out, the rejected ones change nothing
+        assert_eq!(
+            written.lines().collect::<Vec<_>>(),
+            ["Lor ", "ore ", "rem ", "    ", "Lore", "orem", "    "]
+        );
+        assert_eq!(
+            String::from_utf8(log)?,
+            "Ignoring window size 0: it must be at least 1\n\
+             Ignoring window size 5: the field width (4) is smaller\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn static_source_is_not_polled() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-static-{}", process::id()));
+        let mut output = Output::open(path.clone())?;
+        let mut polls_of = |source: TextSource| -> Result<usize> {
+            let polls = Rc::new(Cell::new(0));
+            RunningText::new(
+                TextSource::Counted(Box::new(source), polls.clone()),
+                4,
+                "".to_owned(),
+                "".to_owned(),
+                vec![],
+                true,
+                false,
+            )?
+            .run_ticks(
+                iter::repeat_n((), 5),
+                '\n',
+                &mut output,
+                None::<Vec<u8>>,
+                None,
+            )?;
+            Ok(polls.get())
+        };
+        let string = TextSource::content("Lorem ipsum".to_owned(), "".to_owned(), "".to_owned());
+        let cmd = TextSource::Cmd(Box::new(CmdSource::new(
+            ["echo", "Lorem ipsum"],
+            "".to_owned(),
+            "".to_owned(),
+            false,
+        )));
+        assert!(!string.is_dynamic());
+        assert!(cmd.is_dynamic());
+        assert_eq!(polls_of(string)?, 0);
+        assert_eq!(polls_of(cmd)?, 5);
+        let written = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(written.lines().count(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn set_window() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content("Lorem ipsum".to_owned(), "".to_owned(), "".to_owned()),
+            4,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        assert_text!(text, "Lore", "orem");
+        text.set_window(6)?;
+        assert_text!(text, "rem ip", "em ips");
+        text.set_window(20)?;
+        assert_text!(text, "Lorem ipsum", "Lorem ipsum");
+        text.set_window(2)?;
+        assert_text!(text, "Lo", "or");
+        Ok(())
+    }
+
+    #[test]
+    fn aligned_ticks() {
+        let second = Duration::from_secs(1);
+        let ms = Duration::from_millis;
+        assert_eq!(until_boundary(ms(1_000_250), second), ms(750));
+        assert_eq!(until_boundary(ms(1_999_999), second), ms(1));
+        assert_eq!(until_boundary(ms(2_000_000), second), Duration::ZERO);
+        assert_eq!(until_boundary(ms(1_000_420), ms(100)), ms(80));
+        assert_eq!(
+            until_boundary(ms(1_000_420), Duration::ZERO),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn aligned_wakeups() {
+        let ms = Duration::from_millis;
+        let clock = Cell::new(ms(1_000_250));
+        let wakeups = RefCell::new(vec![]);
+        let ticks = boundary_ticks(
+            ms(100),
+            || clock.get(),
+            |wait| {
+                clock.set(clock.get() + wait);
+                wakeups.borrow_mut().push(clock.get());
+            },
+        );
+        // Work done between ticks: none, less than a period, more than a period.
+        for (work, _) in [0, 0, 30, 99, 100, 250, 0].into_iter().zip(ticks) {
+            clock.set(clock.get() + ms(work));
+        }
+        assert_eq!(
+            wakeups.into_inner(),
+            [1_000_300, 1_000_400, 1_000_500, 1_000_600, 1_000_700, 1_000_800, 1_001_100].map(ms)
+        );
+    }
+
+    #[test]
+    fn verbose_logs_errors() -> Result<()> {
+        let marker = env::temp_dir().join(format!("mergneh-verbose-{}", process::id()));
+        let path = env::temp_dir().join(format!("mergneh-verbose-out-{}", process::id()));
+        let script = format!(
+            "echo >> '{0}'; if [ $(wc -l < '{0}') -gt 2 ]; then printf '\\377'; else echo ok; fi",
+            marker.display()
+        );
+        let mut text = RunningText::new(
+            TextSource::Cmd(Box::new(CmdSource::new(
+                ["sh", "-c", &script],
+                "".to_owned(),
+                "".to_owned(),
+                false,
+            ))),
+            12,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        let mut output = Output::open(path.clone())?;
+        let mut log = Vec::new();
+        assert!(text.tick(&mut output, '\n', Some(&mut log))?);
+        assert!(text.tick(&mut output, '\n', Some(&mut log))?);
+        assert!(text.tick(&mut output, '\n', None::<&mut Vec<u8>>).is_err());
+        fs::remove_file(&marker)?;
+        let written = fs::read_to_string(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(written, "ok\n");
+        assert!(String::from_utf8(log)?
+            .starts_with("Source error: Child process has outputed invalid UTF-8"));
+        Ok(())
+    }
+
+    #[test]
+    fn verbose_logs_diagnostics() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-diagnostics-{}", process::id()));
+        let mut text = RunningText::new(
+            TextSource::Cmd(Box::new(CmdSource::new(
+                ["sh", "-c", "echo out; echo boom >&2; exit 3"],
+                "".to_owned(),
+                "".to_owned(),
+                false,
+            ))),
+            12,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        let mut output = Output::open(path.clone())?;
+        let mut log = Vec::new();
+        assert!(text.tick(&mut output, '\n', Some(&mut log))?);
+        assert!(log.is_empty());
+        text.source.collect_diagnostics();
+        assert!(text.tick(&mut output, '\n', Some(&mut log))?);
+        fs::remove_file(&path)?;
+        assert_eq!(
+            String::from_utf8(log)?,
+            "Command sh -c echo out; echo boom >&2; exit 3 finished with exit status: 3, stderr: \"boom\"\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn from_the_end() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content(
+                "I am a running text".to_owned(),
+                "".to_owned(),
+                "".to_owned(),
+            ),
+            12,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        text.seek_end();
+        assert_text!(text, "running text", "unning text|", "nning text|I");
+        Ok(())
+    }
+
+    #[test]
+    fn start_offset() -> Result<()> {
+        let text = |offset| -> Result<RunningText> {
+            let mut text = RunningText::new(
+                TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+                3,
+                "|".to_owned(),
+                "".to_owned(),
+                vec![],
+                true,
+                false,
+            )?;
+            text.seek_clamped(offset);
+            Ok(text)
+        };
+        let mut near = text(2)?;
+        assert_text!(near, "rem", "em|", "m|L");
+        let mut far = text(100)?;
+        assert_text!(far, "m|L", "|Lo", "Lor");
+        Ok(())
+    }
+
+    #[test]
+    fn word_scroll() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content("one two  three".to_owned(), "".to_owned(), "".to_owned()),
+            9,
+            " ".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?
+        .word_scroll(true);
+        assert_text!(text, "one two  ", "two  thre", "three one", "one two  ");
+        Ok(())
+    }
+
+    #[test]
+    fn window_of_one() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content("ёж y".to_owned(), "".to_owned(), "".to_owned()),
+            1,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        assert_text!(text, "ё", "ж", " ", "y", "|", "ё");
+        let mut text = RunningText::new(
+            TextSource::content("a".to_owned(), "".to_owned(), "".to_owned()),
+            1,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?;
+        assert_text!(text, "a", "a");
+        Ok(())
+    }
+
+    #[test]
+    fn window_of_zero() -> Result<()> {
+        for repeat in [false, true] {
+            let mut text = RunningText::new(
+                TextSource::content("ёж".to_owned(), "".to_owned(), "".to_owned()),
+                0,
+                "|".to_owned(),
+                "".to_owned(),
+                vec![],
+                repeat,
+                false,
+            )?
+            .word_scroll(true);
+            assert_text!(text, "", "", "", "");
+            text.seek_end();
+            assert_text!(text, "");
+        }
+        let mut text = RunningText::new(
+            TextSource::content("abc".to_owned(), "<".to_owned(), ">".to_owned()),
+            0,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        assert_text!(text, "<>", "<>");
+        Ok(())
+    }
+
+    #[test]
+    fn offset_env() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::Cmd(Box::new(CmdSource::new(
+                ["sh", "-c", "printf abcdef$MERGNEH_OFFSET"],
+                "".to_owned(),
+                "".to_owned(),
+                false,
+            ))),
+            3,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?;
+        for (offset, frame) in ["abc", "bcd", "cde"].into_iter().enumerate() {
+            assert_eq!(text.next().unwrap()?, frame);
+            assert_eq!(text.get_raw_content(), format!("abcdef{offset}|"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn skip_identical() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content("a    b".to_owned(), "".to_owned(), "".to_owned()),
+            3,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?
+        .skip_identical(true);
+        assert_text!(text, "a  ", "   ", "  b", " ba", "ba ", "a  ", "   ", "  b");
+        let mut text = RunningText::new(
+            TextSource::content("    ".to_owned(), "".to_owned(), "".to_owned()),
+            3,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?
+        .skip_identical(true);
+        assert_text!(text, "   ", "   ", "   ");
+        Ok(())
+    }
+
+    #[test]
+    fn cycles() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content("abc".to_owned(), "".to_owned(), "".to_owned()),
+            2,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?
+        .cycles(Some(1));
+        assert_text!(text, "ab", "bc", "c|", "|a", "ab", "ab", "ab");
+        Ok(())
+    }
+
+    #[test]
+    fn scroll_once() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content("Lorem ipsum".to_owned(), "[".to_owned(), "]".to_owned()),
+            8,
+            " | ".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?
+        .scroll_once(true)?;
+        assert_text!(
+            text,
+            "[Lorem ip]",
+            "[orem ips]",
+            "[rem ipsu]",
+            "[em ipsum]",
+            "[em ipsum]",
+            "[em ipsum]"
+        );
+        let mut text = RunningText::new(
+            TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+            8,
+            " | ".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?
+        .scroll_once(true)?;
+        assert_text!(text, "Lorem", "Lorem");
+        Ok(())
+    }
+
+    #[test]
+    fn scroll_modes_with_overflow() -> Result<()> {
+        let text = |repeat, once| {
+            RunningText::new(
+                TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+                3,
+                "|".to_owned(),
+                "".to_owned(),
+                vec![],
+                repeat,
+                false,
+            )?
+            .scroll_once(once)
+        };
+        for repeat in [true, false] {
+            let mut text = text(repeat, false)?;
+            assert_eq!(text.get_raw_content(), "Lorem|");
+            assert_text!(text, "Lor", "ore", "rem", "em|", "m|L", "|Lo", "Lor");
+        }
+        let mut once = text(true, true)?;
+        assert_eq!(once.get_raw_content(), "Lorem");
+        assert_text!(once, "Lor", "ore", "rem", "rem");
+        let mut truncated = text(true, false)?.truncate_to_window(true)?;
+        assert_eq!(truncated.get_raw_content(), "Lorem");
+        assert_text!(truncated, "Lor", "Lor", "Lor");
+        Ok(())
+    }
+
+    #[test]
+    fn once_then_clear() -> Result<()> {
+        let text = |content: &str| {
+            RunningText::new(
+                TextSource::content(content.to_owned(), "[".to_owned(), "]".to_owned()),
+                3,
+                " | ".to_owned(),
+                "".to_owned(),
+                vec![],
+                true,
+                false,
+            )?
+            .once_then_clear(true)
+        };
+        let mut long = text("Lorem")?;
+        assert_text!(long, "[Lor]", "[ore]", "[rem]", "", "");
+        let mut short = text("Lo")?;
+        assert_text!(short, "[Lo]", "[Lo]", "[Lo]", "", "");
+        let mut faster = text("Lorem")?.speed(2.0);
+        assert_text!(faster, "[Lor]", "[rem]", "", "");
+        let mut slower = text("Lorem")?.speed(0.5);
+        assert_text!(slower, "[Lor]", "[Lor]", "[ore]", "[ore]", "[rem]", "[rem]", "");
+        let mut short = text("Lo")?.speed(3.0);
+        assert_text!(short, "[Lo]", "", "");
+        Ok(())
+    }
+
+    #[test]
+    fn wrap_boundary() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::content(
+                "I am a running text".to_owned(),
+                "".to_owned(),
+                "".to_owned(),
+            ),
+            25,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?;
+        let mut boundaries = vec![];
+        for i in 0..45 {
+            let frame = text.next().unwrap()?;
+            if text.at_wrap_boundary() {
+                boundaries.push((i, frame));
+            }
+        }
+        assert_eq!(
+            boundaries,
+            [
+                (19, "|I am a running text|I am".to_owned()),
+                (39, "|I am a running text|I am".to_owned())
+            ]
+        );
+        let mut text = RunningText::new(
+            TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+            8,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        text.next().unwrap()?;
+        assert!(!text.at_wrap_boundary());
+        Ok(())
+    }
+
+    #[test]
+    fn speed() -> Result<()> {
+        let text = |speed| {
+            RunningText::new(
+                TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+                3,
+                "|".to_owned(),
+                "".to_owned(),
+                vec![],
+                true,
+                false,
+            )
+            .map(|text| text.speed(speed))
+        };
+        let mut half = text(0.5)?;
+        assert_text!(half, "Lor", "Lor", "ore", "ore", "rem", "rem", "em|");
+        let mut faster = text(1.5)?;
+        assert_text!(faster, "Lor", "ore", "em|", "m|L", "Lor", "ore");
+        Ok(())
+    }
+
+    #[test]
+    fn separator_mode() -> Result<()> {
+        let text = |mode| {
+            RunningText::new(
+                TextSource::content("Lorem".to_owned(), "".to_owned(), "".to_owned()),
+                3,
+                " | ".to_owned(),
+                "".to_owned(),
+                vec![],
+                true,
+                false,
+            )
+            .map(|text| text.separator_mode(mode))
+        };
+        assert_eq!(
+            text(SeparatorMode::InScroll)?.full_cycle()?,
+            ["Lor", "ore", "rem", "em ", "m |", " | ", "| L", " Lo"]
+        );
+        assert_eq!(
+            text(SeparatorMode::GapOnly)?.full_cycle()?,
+            ["Lor", "ore", "rem", "em ", "m |"]
+        );
+        let mut gap_only = text(SeparatorMode::GapOnly)?;
+        gap_only.seek(6);
+        assert_text!(gap_only, "ore");
+        Ok(())
+    }
+
+    #[test]
+    fn full_cycle() -> Result<()> {
+        let text = |content: &str, repeat| {
+            RunningText::new(
+                TextSource::content(content.to_owned(), "".to_owned(), "".to_owned()),
+                2,
+                "|".to_owned(),
+                "".to_owned(),
+                vec![],
+                repeat,
+                false,
+            )
+        };
+        assert_eq!(text("abc", true)?.full_cycle()?, ["ab", "bc", "c|", "|a"]);
+        assert_eq!(text("a", false)?.full_cycle()?, ["a"]);
+        let mut words = text("one two", true)?.word_scroll(true);
+        assert_eq!(words.full_cycle()?, ["on", "tw"]);
+        let mut shifted = text("abc", true)?;
+        shifted.seek(2);
+        assert_eq!(shifted.full_cycle()?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn max_len() -> Result<()> {
+        let long = "ё".repeat(1000);
+        let mut text = RunningText::new(
+            TextSource::content(long.clone(), "".to_owned(), "".to_owned()),
+            60,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?
+        .max_len(50, None)?;
+        assert_eq!(text.next().unwrap()?, "ё".repeat(50));
+        let mut text = RunningText::new(
+            TextSource::content(long, "".to_owned(), "".to_owned()),
+            60,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?
+        .max_len(5, Some("…".to_owned()))?;
+        assert_eq!(text.next().unwrap()?, "ёёёё…");
+        let truncated = |content: &str, max_len, ellipsis| {
+            let mut content = content.to_owned();
+            truncate(&mut content, max_len, ellipsis);
+            content
+        };
+        assert_eq!(truncated("abcdef", 5, Some("...")), "ab...");
+        assert_eq!(truncated("abcde", 5, Some("...")), "abcde");
+        assert_eq!(truncated("abcdef", 2, Some("...")), "..");
+        assert_eq!(truncated("abcdef", 0, Some("...")), "");
+        // "e\u{301}" is one grapheme of two chars, the cut must not separate the accent.
+        assert_eq!(truncated("cafe\u{301}s", 4, None), "cafe\u{301}");
+        assert_eq!(truncated("cafe\u{301}s", 5, None), "cafe\u{301}s");
+        assert_eq!(truncated("👍🏽👍🏽👍🏽", 2, Some("…")), "👍🏽…");
+        Ok(())
+    }
+
+    #[test]
+    fn scripted_changes() -> Result<()> {
+        let script =
+            || TextSource::Scripted(["abcdef", "abcdef", "uvwxyz"].map(str::to_owned).into());
+        let mut text = RunningText::new(
+            script(),
+            3,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?;
+        assert_text!(text, "abc");
+        assert_eq!(text.changes, ContentChange::Running);
+        assert_text!(text, "vwx");
+        assert_eq!(text.changes, ContentChange::Running);
+        assert_text!(text, "wxy");
+        assert!(text.changes.is_empty());
+        let mut text = RunningText::new(
+            script(),
+            3,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            true,
+        )?;
+        assert_text!(text, "abc", "uvw", "vwx");
+        Ok(())
+    }
+
+    #[test]
+    fn empty_dynamic_content() -> Result<()> {
+        let script = ["", "", "abcdef"].map(str::to_owned);
+        let mut text = RunningText::new(
+            TextSource::Scripted(script.into()),
+            3,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?;
+        assert_text!(text, "", "abc", "bcd");
+        let mut text = RunningText::new(
+            TextSource::content("".to_owned(), "".to_owned(), "".to_owned()),
+            3,
+            "".to_owned(),
+            "".to_owned(),
+            vec![],
+            true,
+            false,
+        )?;
+        assert!(text.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn on_change() -> Result<()> {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let script = ["first", "second\nline"].map(str::to_owned);
+        let mut text = RunningText::new(
+            TextSource::Scripted(script.into()),
+            20,
+            "".to_owned(),
+            " ".to_owned(),
+            vec![],
+            false,
+            false,
+        )?
+        .on_change({
+            let seen = seen.clone();
+            move |content| {
+                seen.borrow_mut().push(content.to_owned());
+                Ok(())
+            }
+        });
+        assert_text!(text, "second line", "second line");
+        assert_eq!(*seen.borrow(), ["second\nline"]);
+        text.on_change = Some(OnChange(Box::new(|_| Err(anyhow::anyhow!("hook failed")))));
+        text.source = TextSource::Scripted(["third".to_owned()].into());
+        assert!(text.next().unwrap().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn short_and_long_content() -> Result<()> {
+        let script = ["abcdef", "abcdef", "abcdefg", "ab", "uvwxyz"].map(str::to_owned);
+        let mut text = RunningText::new(
+            TextSource::Scripted(script.into()),
+            4,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?;
+        assert_text!(text, "abcd", "bcde", "ab", "uvwx", "vwxy");
+        Ok(())
+    }
+
+    #[test]
+    fn initial() -> Result<()> {
+        let mut text = RunningText::new(
+            TextSource::Cmd(Box::new(CmdSource::new(
+                ["true"],
+                "".to_owned(),
+                "".to_owned(),
+                false,
+            ))),
+            8,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?
+        .initial(Some("waiting".to_owned()))?;
+        assert_text!(text, "waiting", "waiting");
+        let script = ["", "hello"].map(str::to_owned);
+        let mut text = RunningText::new(
+            TextSource::Scripted(script.into()),
+            8,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![],
+            false,
+            false,
+        )?
+        .initial(Some("waiting".to_owned()))?;
+        assert_eq!(text.get_raw_content(), "waiting|");
+        assert_text!(text, "hello");
+        Ok(())
+    }
+
+    #[test]
+    fn replacement_with_changing_content() -> Result<()> {
+        let marker = env::temp_dir().join(format!("mergneh-replacement-{}", process::id()));
+        let script = format!(
+            "echo >> '{0}'; if [ $(($(wc -l < '{0}') % 2)) -eq 1 ]; then echo 'a & b & c'; else echo 'a b'; fi",
+            marker.display()
+        );
+        let mut text = RunningText::new(
+            TextSource::Cmd(Box::new(CmdSource::new(
+                ["sh", "-c", &script],
+                "".to_owned(),
+                "".to_owned(),
+                false,
+            ))),
+            4,
+            "|".to_owned(),
+            "".to_owned(),
+            vec![("&".to_owned(), "&amp;".to_owned())],
+            false,
+            false,
+        )?;
+        let frames = (0..6)
+            .map(|_| text.next().unwrap())
+            .collect::<Result<Vec<_>>>();
+        fs::remove_file(&marker)?;
+        assert_eq!(
+            frames?,
+            ["a b", "a &amp; ", "a b", "a &amp; ", "a b", "a &amp; "]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,115 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Tick timings accumulated over a window of time, summarized once the window is over.
+#[derive(Debug)]
+pub struct Stats {
+    window: Duration,
+    window_start: Option<Instant>,
+    ticks: u32,
+    work: Duration,
+    max_work: Duration,
+    changes: usize,
+}
+
+/// What happened during one window of `Stats`.
+#[derive(Debug, PartialEq)]
+pub struct Summary {
+    ticks_per_sec: f64,
+    average_work: Duration,
+    max_work: Duration,
+    changes: usize,
+}
+
+impl Stats {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            window_start: None,
+            ticks: 0,
+            work: Duration::ZERO,
+            max_work: Duration::ZERO,
+            changes: 0,
+        }
+    }
+
+    /// Records a tick that started at `start` and finished at `end`. Returns a summary
+    /// of the previous ticks if this one starts after the window is over, it then starts the next one.
+    pub fn record(&mut self, start: Instant, end: Instant, changed: bool) -> Option<Summary> {
+        let window_start = *self.window_start.get_or_insert(start);
+        let elapsed = start.duration_since(window_start);
+        let summary = (elapsed >= self.window && self.ticks > 0).then(|| {
+            let summary = Summary {
+                ticks_per_sec: f64::from(self.ticks) / elapsed.as_secs_f64(),
+                average_work: self.work / self.ticks,
+                max_work: self.max_work,
+                changes: self.changes,
+            };
+            *self = Self {
+                window_start: Some(start),
+                ..Self::new(self.window)
+            };
+            summary
+        });
+        let work = end.duration_since(start);
+        self.ticks += 1;
+        self.work += work;
+        self.max_work = self.max_work.max(work);
+        self.changes += usize::from(changed);
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Stats: {:.1} ticks/s, {:?} of work per tick on average, {:?} at most, {} source changes",
+            self.ticks_per_sec, self.average_work, self.max_work, self.changes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Stats, Summary};
+
+    #[test]
+    fn tick_rate() {
+        let ms = Duration::from_millis;
+        let origin = Instant::now();
+        let mut stats = Stats::new(Duration::from_secs(1));
+        let summaries = (0..25)
+            .filter_map(|i| {
+                let start = origin + ms(100 * i);
+                let work = if i == 3 { ms(20) } else { ms(5) };
+                stats.record(start, start + work, i % 4 == 0)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            [
+                Summary {
+                    ticks_per_sec: 10.0,
+                    average_work: ms(6) + Duration::from_micros(500),
+                    max_work: ms(20),
+                    changes: 3,
+                },
+                Summary {
+                    ticks_per_sec: 10.0,
+                    average_work: ms(5),
+                    max_work: ms(5),
+                    changes: 2,
+                }
+            ]
+        );
+        assert_eq!(
+            summaries[1].to_string(),
+            "Stats: 10.0 ticks/s, 5ms of work per tick on average, 5ms at most, 2 source changes"
+        );
+    }
+}
@@ -1,17 +1,25 @@
 use bitflags::bitflags;
 use clap::{ArgMatches, Id};
 
+#[cfg(test)]
+use std::{cell::Cell, collections::VecDeque, rc::Rc};
 use std::{
     ffi::{OsStr, OsString},
+    fmt::Write,
     fs::{self},
     io::{self},
     path::Path,
 };
 
-use crate::utils::Command;
+use crate::utils::{unescape, Command, Diagnostics, WrapTemplate};
 
+/// Environment variable holding the offset of the next iteration for command sources.
+pub const OFFSET_VAR: &str = "MERGNEH_OFFSET";
+
+#[cfg(feature = "mpd")]
+use crate::mpd::{MpdFormatter, MpdSource, StateStatusIcons, StatusIcons, StatusIconsSet};
 #[cfg(feature = "mpd")]
-use crate::mpd::{MpdFormatter, MpdSource, StatusIconsSet};
+use anyhow::Context;
 
 #[derive(Debug, Clone)]
 pub struct Content {
@@ -25,24 +33,87 @@ pub struct CmdSource {
     pub cmd: Command,
     pub prefix: String,
     pub suffix: String,
+    keep_trailing_newline: bool,
+    wrap: Option<WrapTemplate>,
+    delta: Option<Delta>,
     last_output: String,
 }
 
+/// Replaces numeric output with its difference from the previous one.
+#[derive(Debug)]
+struct Delta {
+    unit: String,
+    /// The previous number and how many decimal places it was written with.
+    last: Option<(f64, usize)>,
+}
+
+impl Delta {
+    /// The first number shows as `+0`, non-numeric output is kept as is and starts over.
+    /// The difference is rounded to the decimal places of the more precise number.
+    fn apply(&mut self, output: &str) -> Option<String> {
+        let output = output.trim();
+        let Ok(value) = output.parse::<f64>() else {
+            self.last = None;
+            return None;
+        };
+        let precision = decimal_places(output);
+        let (last, last_precision) = self.last.unwrap_or((value, precision));
+        self.last = Some((value, precision));
+        Some(format!(
+            "{:+.*}{}",
+            precision.max(last_precision),
+            value - last,
+            self.unit
+        ))
+    }
+}
+
+/// Decimal places a number is written with, an exponent like in `1.5e-3` shifts them.
+fn decimal_places(number: &str) -> usize {
+    let (mantissa, exponent) = number
+        .split_once(['e', 'E'])
+        .map_or((number, 0), |(m, e)| (m, e.parse::<i64>().unwrap_or(0)));
+    let fraction = mantissa.split_once('.').map_or(0, |(_, f)| f.len());
+    (fraction as i64).saturating_sub(exponent).max(0) as usize
+}
+
 impl CmdSource {
     pub fn new<S: AsRef<OsStr>, I: IntoIterator<Item = S>>(
         args: I,
         prefix: String,
         suffix: String,
+        keep_trailing_newline: bool,
     ) -> Self {
         Self {
             cmd: args.into_iter().collect(),
             prefix,
             suffix,
+            keep_trailing_newline,
+            wrap: None,
+            delta: None,
             last_output: String::new(),
         }
     }
+    pub fn wrap(mut self, wrap: Option<WrapTemplate>) -> Self {
+        self.wrap = wrap;
+        self
+    }
+    /// Shows the difference between consecutive numbers followed by `unit` instead of the output.
+    pub fn delta(mut self, unit: Option<String>) -> Self {
+        self.delta = unit.map(|unit| Delta { unit, last: None });
+        self
+    }
     pub fn get(&mut self, content: &mut String) -> anyhow::Result<ContentChange> {
-        let output = self.cmd.spawn_and_read_output()?;
+        let mut output = self.cmd.spawn_and_read_output()?;
+        if !self.keep_trailing_newline {
+            trim_trailing_newline(&mut output);
+        }
+        if let Some(diff) = self.delta.as_mut().and_then(|delta| delta.apply(&output)) {
+            output = diff;
+        }
+        if let Some(wrap) = &self.wrap {
+            output = wrap.apply(&output);
+        }
         if self.last_output == output {
             Ok(ContentChange::empty())
         } else {
@@ -55,6 +126,7 @@ impl CmdSource {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub struct ContentChange: u8 {
         const Running = 1;
         const Prefix = 1 << 1;
@@ -65,9 +137,16 @@ bitflags! {
 #[derive(Debug)]
 pub enum TextSource {
     String(Content),
-    Cmd(CmdSource),
+    Cmd(Box<CmdSource>),
     #[cfg(feature = "mpd")]
     Mpd(Box<MpdSource>),
+    /// Each poll replaces the content with the next one from the script,
+    /// the last one stays once the script runs out.
+    #[cfg(test)]
+    Scripted(VecDeque<String>),
+    /// Wraps another source and counts how many times it is polled.
+    #[cfg(test)]
+    Counted(Box<TextSource>, Rc<Cell<usize>>),
 }
 
 impl TextSource {
@@ -78,11 +157,42 @@ impl TextSource {
             suffix,
         })
     }
+    /// Makes command sources keep `Diagnostics` of each execution.
+    pub fn collect_diagnostics(&mut self) {
+        if let TextSource::Cmd(s) = self {
+            s.cmd.collect_diagnostics();
+        }
+    }
+    /// Diagnostics of the last command execution if they are collected.
+    pub fn diagnostics(&self) -> Option<(&Command, &Diagnostics)> {
+        match self {
+            TextSource::Cmd(s) => s.cmd.diagnostics().map(|d| (&s.cmd, d)),
+            _ => None,
+        }
+    }
+    /// Whether polling the source can ever change the content, a string never does.
+    pub fn is_dynamic(&self) -> bool {
+        match self {
+            TextSource::String(_) => false,
+            #[cfg(test)]
+            TextSource::Counted(source, _) => source.is_dynamic(),
+            _ => true,
+        }
+    }
     pub fn get_initial_content(&mut self) -> anyhow::Result<Content> {
         match self {
             TextSource::String(c) => Ok(c.clone()),
+            #[cfg(test)]
+            TextSource::Counted(source, _) => source.get_initial_content(),
+            #[cfg(test)]
+            TextSource::Scripted(script) => Ok(Content {
+                running: script.pop_front().unwrap_or_default(),
+                prefix: String::new(),
+                suffix: String::new(),
+            }),
             TextSource::Cmd(s) => {
                 let mut output = String::new();
+                s.cmd.env(OFFSET_VAR, "0");
                 s.get(&mut output)?;
                 Ok(Content {
                     running: output,
@@ -97,7 +207,10 @@ impl TextSource {
                     prefix: String::new(),
                     suffix: String::new(),
                 };
-                c.running_format()
+                if c.is_hidden() {
+                    return Ok(content);
+                }
+                c.current_format()
                     .format_with_source(c, &mut content.running)?;
                 c.prefix_format()
                     .format_with_source(c, &mut content.prefix)?;
@@ -107,17 +220,62 @@ impl TextSource {
             }
         }
     }
+    pub fn explain<W: Write>(&self, f: &mut W) -> std::fmt::Result {
+        match self {
+            TextSource::String(_) => writeln!(f, "source: string"),
+            #[cfg(test)]
+            TextSource::Scripted(_) => writeln!(f, "source: scripted"),
+            #[cfg(test)]
+            TextSource::Counted(source, _) => source.explain(f),
+            TextSource::Cmd(s) => writeln!(f, "source: command {}", s.cmd),
+            #[cfg(feature = "mpd")]
+            TextSource::Mpd(s) => {
+                writeln!(f, "source: mpd")?;
+                writeln!(f, "format: {}", s.running_format())?;
+                if let Some(stopped) = s.stopped_format() {
+                    writeln!(f, "stopped format: {}", stopped)?;
+                }
+                writeln!(f, "prefix format: {}", s.prefix_format())?;
+                writeln!(f, "suffix format: {}", s.suffix_format())
+            }
+        }
+    }
+    /// Polls the source, `offset` is where the next iteration starts.
     pub fn get_content(
         &mut self,
+        offset: usize,
         content: &mut String,
         #[cfg(feature = "mpd")] prefix: &mut String,
         #[cfg(feature = "mpd")] suffix: &mut String,
     ) -> anyhow::Result<ContentChange> {
         match self {
             TextSource::String(_) => Ok(ContentChange::empty()),
+            #[cfg(test)]
+            TextSource::Scripted(script) => match script.pop_front() {
+                Some(next) => {
+                    *content = next;
+                    Ok(ContentChange::Running)
+                }
+                None => Ok(ContentChange::empty()),
+            },
+            #[cfg(test)]
+            TextSource::Counted(source, polls) => {
+                polls.set(polls.get() + 1);
+                source.get_content(
+                    offset,
+                    content,
+                    #[cfg(feature = "mpd")]
+                    prefix,
+                    #[cfg(feature = "mpd")]
+                    suffix,
+                )
+            }
             #[cfg(feature = "mpd")]
             TextSource::Mpd(s) => s.get(content, prefix, suffix),
-            TextSource::Cmd(s) => s.get(content),
+            TextSource::Cmd(s) => {
+                s.cmd.env(OFFSET_VAR, offset.to_string());
+                s.get(content)
+            }
         }
     }
 }
@@ -130,49 +288,239 @@ impl TryFrom<&mut ArgMatches> for TextSource {
         let src = value.try_remove_one::<String>(kind.as_str());
         let prefix = value.remove_one::<String>("prefix").unwrap();
         let suffix = value.remove_one::<String>("suffix").unwrap();
-        return Ok(match kind.as_str() {
-            "SOURCE" => {
-                TextSource::content(from_file_or_string(&src.unwrap().unwrap())?, prefix, suffix)
-            }
-            "file" => {
-                TextSource::content(fs::read_to_string(src.unwrap().unwrap())?, prefix, suffix)
-            }
-            "string" => TextSource::content(src.unwrap().unwrap(), prefix, suffix),
-            "stdin" => TextSource::content(io::read_to_string(io::stdin())?, prefix, suffix),
-            "cmd" => TextSource::Cmd(CmdSource::new(
-                value.remove_many::<OsString>(kind.as_str()).unwrap(),
+        let lossy = value.remove_one::<bool>("lossy").unwrap();
+        let wrap = value.remove_one::<WrapTemplate>("wrap");
+        let mut source = match kind.as_str() {
+            "SOURCE" => TextSource::content(
+                from_file_or_string(&src.unwrap().unwrap(), lossy)?,
                 prefix,
                 suffix,
+            ),
+            "file" => TextSource::content(read_file(src.unwrap().unwrap(), lossy)?, prefix, suffix),
+            "string" => {
+                let string = src.unwrap().unwrap();
+                let string = if value.remove_one::<bool>("string-escapes").unwrap() {
+                    unescape(&string)?
+                } else {
+                    string
+                };
+                TextSource::content(string, prefix, suffix)
+            }
+            "stdin" => TextSource::content(io::read_to_string(io::stdin())?, prefix, suffix),
+            "cmd" => TextSource::Cmd(Box::new(
+                CmdSource::new(
+                    value
+                        .remove_many::<OsString>(kind.as_str())
+                        .unwrap()
+                        .map(unescape_terminator),
+                    prefix,
+                    suffix,
+                    value.remove_one("keep-trailing-newline").unwrap(),
+                )
+                .wrap(wrap.clone())
+                .delta(value.remove_one("delta")),
             )),
             #[cfg(feature = "mpd")]
-            "mpd" => TextSource::Mpd(Box::new(MpdSource::new(
-                value.try_remove_one(kind.as_str()).unwrap().unwrap(),
-                value.remove_one("format").unwrap(),
-                value
-                    .remove_one("prefix-format")
-                    .unwrap_or(MpdFormatter::only_string(prefix)),
-                value
-                    .remove_one("suffix-format")
-                    .unwrap_or(MpdFormatter::only_string(suffix)),
-                StatusIconsSet::new(
-                    value.remove_one("status-icons").unwrap(),
-                    value.remove_one("consume-icons").unwrap(),
-                    value.remove_one("random-icons").unwrap(),
-                    value.remove_one("repeat-icons").unwrap(),
-                    value.remove_one("single-icons").unwrap(),
-                ),
-                value.remove_one("default-placeholder").unwrap(),
-            )?)),
+            "mpd" => {
+                let separator = value.remove_one::<String>("icon-separator");
+                let separator = separator.as_deref();
+                let status_icons = value
+                    .remove_one::<String>("status-icons")
+                    .map(|s| StateStatusIcons::parse(&s, separator))
+                    .transpose()
+                    .context("Invalid --status-icons")?;
+                let mut icons = |id: &str| {
+                    value
+                        .remove_one::<String>(id)
+                        .map(|s| StatusIcons::parse(&s, separator))
+                        .transpose()
+                        .with_context(|| format!("Invalid --{id}"))
+                };
+                let (consume, random, repeat, single) = (
+                    icons("consume-icons")?,
+                    icons("random-icons")?,
+                    icons("repeat-icons")?,
+                    icons("single-icons")?,
+                );
+                let icons = StatusIconsSet::themed(
+                    value.remove_one("icon-theme").unwrap(),
+                    status_icons,
+                    consume,
+                    random,
+                    repeat,
+                    single,
+                );
+                TextSource::Mpd(Box::new(
+                    MpdSource::new(
+                        value.try_remove_one(kind.as_str()).unwrap().unwrap(),
+                        value.remove_one("format").unwrap(),
+                        value.remove_one("stopped-format"),
+                        value
+                            .remove_one("prefix-format")
+                            .unwrap_or(MpdFormatter::only_string(prefix)),
+                        value
+                            .remove_one("suffix-format")
+                            .unwrap_or(MpdFormatter::only_string(suffix)),
+                        icons,
+                        value.remove_one("default-placeholder").unwrap(),
+                    )?
+                    .hide_when_stopped(value.remove_one("hide-when-stopped").unwrap())
+                    .escape_braces(value.remove_one("escape-braces").unwrap()),
+                ))
+            }
             _ => unreachable!(),
-        });
+        };
+        if let (TextSource::String(content), Some(wrap)) = (&mut source, wrap) {
+            content.running = wrap.apply(&content.running);
+        }
+        Ok(source)
     }
 }
 
-fn from_file_or_string(arg: &str) -> io::Result<String> {
+fn from_file_or_string(arg: &str, lossy: bool) -> io::Result<String> {
     let path = Path::new(arg);
     Ok(if path.is_file() {
-        fs::read_to_string(path)?
+        read_file(path, lossy)?
     } else {
         arg.to_owned()
     })
 }
+
+/// `\;` stands for a literal `;` argument since a bare one terminates `--cmd`.
+fn unescape_terminator(arg: OsString) -> OsString {
+    match arg.to_str() {
+        Some(s) if s.starts_with('\\') && s.trim_start_matches('\\') == ";" => s[1..].into(),
+        _ => arg,
+    }
+}
+
+/// Reads a file as UTF-8, invalid sequences are replaced with `�` if `lossy` is set.
+fn read_file<P: AsRef<Path>>(path: P, lossy: bool) -> io::Result<String> {
+    if lossy {
+        Ok(String::from_utf8_lossy(&fs::read(path)?).into_owned())
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+/// Removes a single trailing `\n` or `\r\n`.
+fn trim_trailing_newline(output: &mut String) {
+    if output.ends_with('\n') {
+        output.pop();
+        if output.ends_with('\r') {
+            output.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use anyhow::Result;
+
+    use super::{read_file, unescape_terminator, CmdSource, ContentChange, TextSource};
+    use crate::utils::WrapTemplate;
+
+    #[test]
+    fn trailing_newline() -> Result<()> {
+        let mut content = String::new();
+        let mut source =
+            CmdSource::new(["printf", "hi\\n\\n"], "".to_owned(), "".to_owned(), false);
+        assert_eq!(source.get(&mut content)?, ContentChange::Running);
+        assert_eq!(content, "hi\n");
+        let mut source =
+            CmdSource::new(["printf", "hi\\r\\n"], "".to_owned(), "".to_owned(), false);
+        source.get(&mut content)?;
+        assert_eq!(content, "hi");
+        let mut source = CmdSource::new(["printf", "hi\\n"], "".to_owned(), "".to_owned(), true);
+        source.get(&mut content)?;
+        assert_eq!(content, "hi\n");
+        Ok(())
+    }
+
+    #[test]
+    fn dynamic_sources() {
+        let string = TextSource::content("a".to_owned(), "".to_owned(), "".to_owned());
+        let cmd = TextSource::Cmd(Box::new(CmdSource::new(
+            ["true"],
+            "".to_owned(),
+            "".to_owned(),
+            false,
+        )));
+        assert!(!string.is_dynamic());
+        assert!(cmd.is_dynamic());
+        assert!(TextSource::Scripted(["a".to_owned()].into()).is_dynamic());
+    }
+
+    #[test]
+    fn lossy_file() -> Result<()> {
+        let path = env::temp_dir().join(format!("mergneh-lossy-{}", process::id()));
+        fs::write(&path, b"caf\xe9 latin-1")?;
+        let strict = read_file(&path, false);
+        let lossy = read_file(&path, true);
+        fs::remove_file(&path)?;
+        assert!(strict.is_err());
+        assert_eq!(lossy?, "caf\u{fffd} latin-1");
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_terminator() {
+        for (arg, unescaped) in [(r"\;", ";"), (r"\\;", r"\;"), (r"\n", r"\n"), ("a;", "a;")] {
+            assert_eq!(unescape_terminator(arg.into()), unescaped);
+        }
+    }
+
+    #[test]
+    fn wrap() -> Result<()> {
+        let wrap = "Items: {content} ({lines} lines, {len} chars) {{}}".parse::<WrapTemplate>()?;
+        let mut content = String::new();
+        let mut source = CmdSource::new(
+            ["printf", "a\\nb\\nc\\n"],
+            "".to_owned(),
+            "".to_owned(),
+            false,
+        )
+        .wrap(Some(wrap));
+        source.get(&mut content)?;
+        assert_eq!(content, "Items: a\nb\nc (3 lines, 5 chars) {}");
+        assert!("{content".parse::<WrapTemplate>().is_err());
+        assert!("{size}".parse::<WrapTemplate>().is_err());
+        assert!("}".parse::<WrapTemplate>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn delta() -> Result<()> {
+        let counter = env::temp_dir().join(format!("mergneh-delta-{}", process::id()));
+        fs::write(&counter, "100\n")?;
+        let mut content = String::new();
+        let mut source = CmdSource::new(
+            ["cat", counter.to_str().unwrap()],
+            "".to_owned(),
+            "".to_owned(),
+            false,
+        )
+        .delta(Some(" B".to_owned()));
+        let mut deltas = vec![];
+        for next in [
+            "150", "150", "162.5", "n/a", "10", "4", "0.3", "0.1", "1e-3",
+        ] {
+            source.get(&mut content)?;
+            deltas.push(content.clone());
+            fs::write(&counter, next)?;
+        }
+        source.get(&mut content)?;
+        deltas.push(content.clone());
+        fs::remove_file(&counter)?;
+        assert_eq!(
+            deltas,
+            [
+                "+0 B", "+50 B", "+0 B", "+12.5 B", "n/a", "+0 B", "-6 B", "-3.7 B", "-0.2 B",
+                "-0.099 B"
+            ]
+        );
+        Ok(())
+    }
+}
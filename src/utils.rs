@@ -3,21 +3,27 @@ use std::{
     ffi::OsStr,
     fmt::Display,
     io,
-    iter::repeat,
-    process::{self, Child, Stdio},
+    iter::repeat_n,
+    process::{self, Child, ExitStatus, Stdio},
+    str::FromStr,
     string::FromUtf8Error,
 };
 
+use terminal_size::{terminal_size_of, Width};
+
 pub fn replace_newline(text: &mut String, replacement: &str) {
     text.retain(|c| c != '\r');
     if replacement.is_empty() {
         text.retain(|c| c != '\n');
         return;
     }
+    if replacement == "\n" {
+        return;
+    }
     let newline_count = text.chars().filter(|&c| c == '\n').count();
     let additional_len = (replacement.len() - 1) * newline_count;
     text.reserve(additional_len);
-    text.extend(repeat('\0').take(additional_len));
+    text.extend(repeat_n('\0', additional_len));
 
     let mut dest = text.len();
     let mut src = text.len() - additional_len;
@@ -38,8 +44,195 @@ pub fn replace_newline(text: &mut String, replacement: &str) {
     }
 }
 
+/// Width of the terminal that stdout or stderr is attached to, `None` if neither is one.
+pub fn terminal_columns() -> Option<usize> {
+    terminal_size_of(io::stdout())
+        .or_else(|| terminal_size_of(io::stderr()))
+        .map(|(Width(columns), _)| usize::from(columns))
+        .filter(|&columns| columns > 0)
+}
+
 #[derive(Debug)]
-pub struct Command(process::Command);
+pub enum UnescapeError {
+    TrailingBackslash,
+    UnknownEscape(char),
+    InvalidUnicode(String),
+}
+
+impl Error for UnescapeError {}
+
+impl Display for UnescapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnescapeError::TrailingBackslash => write!(f, "Unterminated escape sequence"),
+            UnescapeError::UnknownEscape(c) => write!(f, "Unknown escape sequence '\\{}'", c),
+            UnescapeError::InvalidUnicode(s) => write!(f, "Invalid unicode escape '\\u{}'", s),
+        }
+    }
+}
+
+/// Interprets `\n`, `\t`, `\r`, `\\` and `\uXXXX` escapes.
+pub fn unescape(text: &str) -> Result<String, UnescapeError> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next().ok_or(UnescapeError::TrailingBackslash)? {
+            'n' => result.push('\n'),
+            't' => result.push('\t'),
+            'r' => result.push('\r'),
+            '\\' => result.push('\\'),
+            c @ (',' | '=') => result.push(c),
+            'u' => {
+                let code = chars.by_ref().take(4).collect::<String>();
+                result.push(
+                    u32::from_str_radix(&code, 16)
+                        .ok()
+                        .filter(|_| code.len() == 4)
+                        .and_then(char::from_u32)
+                        .ok_or(UnescapeError::InvalidUnicode(code))?,
+                );
+            }
+            c => return Err(UnescapeError::UnknownEscape(c)),
+        }
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum WrapToken {
+    Literal(String),
+    Content,
+    Len,
+    Lines,
+}
+
+/// Template around the contents of a generic source, `{content}` is the contents,
+/// `{len}` is its length in characters and `{lines}` is its number of lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrapTemplate(Vec<WrapToken>);
+
+#[derive(Debug)]
+pub enum WrapParseError {
+    UnknownToken(String),
+    UnmatchedBrace,
+}
+
+impl Error for WrapParseError {}
+
+impl Display for WrapParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WrapParseError::UnknownToken(t) => {
+                write!(
+                    f,
+                    "Unknown token '{{{}}}' (expected content, len or lines)",
+                    t
+                )
+            }
+            WrapParseError::UnmatchedBrace => write!(f, "Unmatched '{{' or '}}'"),
+        }
+    }
+}
+
+impl WrapTemplate {
+    pub fn apply(&self, content: &str) -> String {
+        let mut result = String::with_capacity(content.len());
+        for token in &self.0 {
+            match token {
+                WrapToken::Literal(s) => result.push_str(s),
+                WrapToken::Content => result.push_str(content),
+                WrapToken::Len => result.push_str(&content.chars().count().to_string()),
+                WrapToken::Lines => result.push_str(&content.lines().count().to_string()),
+            }
+        }
+        result
+    }
+}
+
+impl FromStr for WrapTemplate {
+    type Err = WrapParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(WrapParseError::UnmatchedBrace),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        tokens.push(WrapToken::Literal(std::mem::take(&mut literal)));
+                    }
+                    tokens.push(match name.as_str() {
+                        "content" => WrapToken::Content,
+                        "len" => WrapToken::Len,
+                        "lines" => WrapToken::Lines,
+                        _ => return Err(WrapParseError::UnknownToken(name)),
+                    });
+                }
+                '}' => return Err(WrapParseError::UnmatchedBrace),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            tokens.push(WrapToken::Literal(literal));
+        }
+        Ok(Self(tokens))
+    }
+}
+
+/// Number of characters of stderr kept in `Diagnostics`.
+const STDERR_LIMIT: usize = 200;
+
+#[derive(Debug)]
+pub struct Command {
+    inner: process::Command,
+    collect_diagnostics: bool,
+    diagnostics: Option<Diagnostics>,
+}
+
+/// Exit status and the beginning of stderr of the last execution.
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub status: ExitStatus,
+    pub stderr: String,
+}
+
+impl Diagnostics {
+    /// Whether the command failed or complained about something.
+    pub fn is_notable(&self) -> bool {
+        !self.status.success() || !self.stderr.is_empty()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.status)?;
+        if !self.stderr.is_empty() {
+            write!(f, ", stderr: {:?}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug)]
 pub enum CommandError {
@@ -59,15 +252,48 @@ impl Display for CommandError {
 }
 
 impl Command {
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, value: V) -> &mut Self {
+        self.inner.env(key, value);
+        self
+    }
+    /// Captures stderr instead of passing it through and keeps `Diagnostics` of each execution.
+    pub fn collect_diagnostics(&mut self) -> &mut Self {
+        self.inner.stderr(Stdio::piped());
+        self.collect_diagnostics = true;
+        self
+    }
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        self.diagnostics.as_ref()
+    }
     pub fn spawn_and_read_output(&mut self) -> Result<String, CommandError> {
-        String::from_utf8(
-            self.0
-                .spawn()
-                .and_then(Child::wait_with_output)
-                .map_err(CommandError::Io)?
-                .stdout,
-        )
-        .map_err(CommandError::UTF8)
+        let output = self
+            .inner
+            .spawn()
+            .and_then(Child::wait_with_output)
+            .map_err(CommandError::Io)?;
+        if self.collect_diagnostics {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim_end();
+            let mut truncated = stderr.chars().take(STDERR_LIMIT).collect::<String>();
+            if truncated.len() < stderr.len() {
+                truncated.push('…');
+            }
+            self.diagnostics = Some(Diagnostics {
+                status: output.status,
+                stderr: truncated,
+            });
+        }
+        String::from_utf8(output.stdout).map_err(CommandError::UTF8)
+    }
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.inner.get_program().to_string_lossy())?;
+        for arg in self.inner.get_args() {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        Ok(())
     }
 }
 
@@ -79,18 +305,22 @@ impl<S: AsRef<OsStr>> FromIterator<S> for Command {
                 .expect("Iterator for Command must have at least one element"),
         );
         cmd.stdout(Stdio::piped()).args(iter);
-        Command(cmd)
+        Command::from(cmd)
     }
 }
 
 impl From<Command> for process::Command {
     fn from(val: Command) -> Self {
-        val.0
+        val.inner
     }
 }
 
 impl From<process::Command> for Command {
     fn from(value: process::Command) -> Self {
-        Command(value)
+        Command {
+            inner: value,
+            collect_diagnostics: false,
+            diagnostics: None,
+        }
     }
 }